@@ -59,6 +59,8 @@ where
     Ok(config)
 }
 
+/// Replace the contents of a config file. The file is left as it was if the config can not be
+/// written.
 #[cfg(feature = "storage")]
 pub fn write_config_file<T>(
     config: &T,
@@ -67,7 +69,10 @@ pub fn write_config_file<T>(
 where
     T: Serialize,
 {
-    write_config(config, &mut file.write_text()?)
+    use crate::storage::Commit;
+    let mut write = file.write_text()?;
+    write_config(config, &mut write)?;
+    write.commit()
 }
 
 #[cfg(feature = "storage")]
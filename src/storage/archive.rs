@@ -0,0 +1,343 @@
+//! Read-only directories backed by zip archives, for level packs and mods distributed as single
+//! files.
+//!
+//! An archive can be opened from anything readable and seekable, from a path, or from a file in
+//! other storage (which reads the whole archive into memory, as is needed on web). Directories in
+//! the archive are reached with [ParentDir::subdir]. Archive entries whose names are not valid
+//! storage names can not be reached. [Metadata] gives the uncompressed size but no modification
+//! time, since zip times have no time zone.
+//!
+//! ```rust
+//! use std::io::{Read, Write};
+//! use dias::storage::archive::ZipDir;
+//! use dias::storage::{Dir, File, MemoryStorage, ParentDir, Storage, WritableDir, WritableFile};
+//!
+//! # let mut storage = MemoryStorage::new();
+//! # let mut pack = storage.writable_data().unwrap().writable_file("pack.zip".into());
+//! # let mut write = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+//! # write.start_file("levels/1.txt", zip::write::SimpleFileOptions::default()).unwrap();
+//! # write.write_all(b"start").unwrap();
+//! # let mut pack_write = pack.write_binary().unwrap();
+//! # pack_write.write_all(&write.finish().unwrap().into_inner()).unwrap();
+//! # dias::storage::Commit::commit(&mut pack_write).unwrap();
+//! let pack = ZipDir::read_from(&storage.data().unwrap().file("pack.zip".into())).unwrap();
+//! let mut text = String::new();
+//! pack.subdir("levels".into())
+//!     .file("1.txt".into())
+//!     .read_text()
+//!     .unwrap()
+//!     .read_to_string(&mut text)
+//!     .unwrap();
+//! assert_eq!(text, "start");
+//! ```
+
+use crate::storage::error::poisoned;
+use crate::storage::generic::{Dir, Entry, File, Metadata, ParentDir, Watcher};
+use crate::storage::keys::{entries_under, join};
+use crate::storage::name::{validate_name, InvalidNameError};
+use std::borrow::Cow;
+use std::io::{Cursor, Read, Seek};
+use std::sync::{Arc, Mutex, MutexGuard};
+use zip::ZipArchive;
+
+static SEP: char = '/';
+/// Most space reserved before reading a file, since archives can claim any size.
+const MAX_RESERVE: u64 = 1 << 20;
+
+struct Archive<R> {
+    zip: Mutex<ZipArchive<R>>,
+    names: Vec<String>,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    fn zip(&self) -> std::io::Result<MutexGuard<'_, ZipArchive<R>>> {
+        self.zip.lock().map_err(poisoned)
+    }
+
+    fn has_file(&self, path: &str) -> bool {
+        self.names.iter().any(|name| name == path)
+    }
+}
+
+pub struct ZipFile<R> {
+    archive: Arc<Archive<R>>,
+    path: Result<String, InvalidNameError>,
+}
+
+impl<R> ZipFile<R> {
+    fn path(&self) -> std::io::Result<&String> {
+        self.path.as_ref().map_err(|e| e.clone().into())
+    }
+}
+
+impl<R: Read + Seek> ZipFile<R> {
+    fn read(&self) -> std::io::Result<Cursor<Vec<u8>>> {
+        let mut zip = self.archive.zip()?;
+        let mut file = zip.by_name(self.path()?)?;
+        let mut contents = Vec::with_capacity(file.size().min(MAX_RESERVE) as usize);
+        file.read_to_end(&mut contents)?;
+        Ok(Cursor::new(contents))
+    }
+}
+
+impl<R: Read + Seek> File for ZipFile<R> {
+    type ReadText = Cursor<Vec<u8>>;
+    type ReadBinary = Cursor<Vec<u8>>;
+
+    fn exists(&self) -> std::io::Result<bool> {
+        Ok(self.archive.has_file(self.path()?))
+    }
+
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        let mut zip = self.archive.zip()?;
+        let file = zip.by_name(self.path()?)?;
+        Ok(Metadata {
+            len: file.size(),
+            modified: None,
+        })
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.path()?;
+        Ok(Watcher::new(|| Ok(Vec::new())))
+    }
+
+    fn read_text(&self) -> std::io::Result<Self::ReadText> {
+        self.read()
+    }
+
+    fn read_binary(&self) -> std::io::Result<Self::ReadBinary> {
+        self.read()
+    }
+}
+
+pub struct ZipDir<R> {
+    archive: Arc<Archive<R>>,
+    path: Result<String, InvalidNameError>,
+}
+
+impl<R: Read + Seek> ZipDir<R> {
+    /// The root directory of an archive.
+    pub fn new(read: R) -> std::io::Result<Self> {
+        let zip = ZipArchive::new(read)?;
+        let names = zip
+            .file_names()
+            .filter(|name| !name.ends_with(SEP))
+            .map(String::from)
+            .collect();
+        Ok(Self {
+            archive: Arc::new(Archive {
+                zip: Mutex::new(zip),
+                names,
+            }),
+            path: Ok("".to_string()),
+        })
+    }
+
+    fn path(&self) -> std::io::Result<&String> {
+        self.path.as_ref().map_err(|e| e.clone().into())
+    }
+}
+
+impl ZipDir<Cursor<Vec<u8>>> {
+    /// Open an archive held in memory.
+    pub fn from_bytes(contents: Vec<u8>) -> std::io::Result<Self> {
+        Self::new(Cursor::new(contents))
+    }
+
+    /// Open an archive stored in a file from any storage, reading it all into memory.
+    pub fn read_from(file: &impl File) -> std::io::Result<Self> {
+        let mut contents = Vec::new();
+        file.read_binary()?.read_to_end(&mut contents)?;
+        Self::from_bytes(contents)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ZipDir<std::io::BufReader<std::fs::File>> {
+    /// Open an archive from a path, reading entries from the file as they are needed.
+    pub fn open(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        Self::new(std::io::BufReader::new(std::fs::File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> Dir for ZipDir<R> {
+    type File = ZipFile<R>;
+
+    fn file(&self, name: Cow<'static, str>) -> Self::File {
+        ZipFile {
+            archive: self.archive.clone(),
+            path: join(&self.path, &name),
+        }
+    }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        let zip = self.archive.zip()?;
+        let mut entries = entries_under(self.path()?, SEP, zip.file_names());
+        // Entries with invalid names could not be opened.
+        entries.retain(|entry| validate_name(&entry.name).is_ok());
+        Ok(entries)
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.path()?;
+        Ok(Watcher::new(|| Ok(Vec::new())))
+    }
+}
+
+impl<R: Read + Seek> ParentDir for ZipDir<R> {
+    type LeafDir = Self;
+
+    fn subdir(&self, name: Cow<'static, str>) -> Self {
+        let path = join(&self.path, &name).map(|mut path| {
+            path.push(SEP);
+            path
+        });
+        Self {
+            archive: self.archive.clone(),
+            path,
+        }
+    }
+
+    fn into_leaf(self) -> Self::LeafDir {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::generic::tests::write_binary;
+    use crate::storage::{MemoryStorage, Storage, WritableDir};
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    fn make_zip() -> Vec<u8> {
+        let mut write = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        write.start_file("readme.txt", options).unwrap();
+        write.write_all(b"hello").unwrap();
+        write.add_directory("levels/", options).unwrap();
+        write.add_directory("levels/empty/", options).unwrap();
+        write
+            .start_file(
+                "levels/1.txt",
+                options.compression_method(CompressionMethod::Deflated),
+            )
+            .unwrap();
+        write.write_all(&b"one".repeat(100)).unwrap();
+        write.start_file("levels/extra/2.txt", options).unwrap();
+        write.write_all(b"two").unwrap();
+        write.start_file("levels/nul.txt", options).unwrap();
+        write.start_file("CON/3.txt", options).unwrap();
+        write.finish().unwrap().into_inner()
+    }
+
+    fn read(file: &impl File) -> String {
+        let mut got = String::new();
+        file.read_text().unwrap().read_to_string(&mut got).unwrap();
+        got
+    }
+
+    #[test]
+    fn reading() {
+        let root = ZipDir::from_bytes(make_zip()).unwrap();
+        assert_eq!(read(&root.file("readme.txt".into())), "hello");
+        let levels = root.subdir("levels".into());
+        assert_eq!(read(&levels.file("1.txt".into())), "one".repeat(100));
+        assert_eq!(levels.file("1.txt".into()).metadata().unwrap().len, 300);
+        assert_eq!(
+            read(&levels.subdir("extra".into()).file("2.txt".into())),
+            "two"
+        );
+        assert!(!levels.file("3.txt".into()).exists().unwrap());
+        assert!(!root.file("levels".into()).exists().unwrap());
+        assert_eq!(
+            levels
+                .file("3.txt".into())
+                .read_text()
+                .err()
+                .unwrap()
+                .kind(),
+            std::io::ErrorKind::NotFound
+        );
+        assert_eq!(
+            root.file("../readme.txt".into())
+                .exists()
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn listing() {
+        let root = ZipDir::from_bytes(make_zip()).unwrap();
+        assert_eq!(
+            root.entries().unwrap(),
+            vec![Entry::dir("levels"), Entry::file("readme.txt")]
+        );
+        assert_eq!(
+            root.subdir("levels".into()).entries().unwrap(),
+            vec![
+                Entry::file("1.txt"),
+                Entry::dir("empty"),
+                Entry::dir("extra")
+            ]
+        );
+        assert_eq!(
+            root.subdir("levels".into())
+                .subdir("empty".into())
+                .entries()
+                .unwrap(),
+            vec![]
+        );
+        assert_eq!(
+            root.subdir("levels".into())
+                .file("nul.txt".into())
+                .exists()
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn from_storage() {
+        let mut storage = MemoryStorage::new();
+        write_binary(
+            &mut storage
+                .writable_data()
+                .unwrap()
+                .writable_file("pack.zip".into()),
+            &make_zip(),
+        );
+        let root = ZipDir::read_from(&storage.data().unwrap().file("pack.zip".into())).unwrap();
+        assert_eq!(read(&root.file("readme.txt".into())), "hello");
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            ZipDir::from_bytes(b"not a zip".to_vec())
+                .err()
+                .unwrap()
+                .kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn from_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pack.zip");
+        std::fs::write(&path, make_zip()).unwrap();
+        let root = ZipDir::open(&path).unwrap();
+        assert_eq!(
+            read(&root.subdir("levels".into()).file("1.txt".into())),
+            "one".repeat(100)
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Async counterparts of the storage traits, for backends which can not block and for code which
+//! wants to await storage the same way on every platform.
+//!
+//! Files are read and written whole, since there is no standard async equivalent of [Read] and
+//! [Write]. Getting files and directories never waits, only using them does.
+//!
+//! Any synchronous storage, directory or file can be used through [AsyncAdapter], which does the
+//! work straight away and gives futures which are already finished.
+//!
+//! ```rust
+//! use dias::storage::asynchronous::{
+//!     AsyncAdapter, AsyncDir, AsyncFile, AsyncStorage, AsyncWritableDir, AsyncWritableFile,
+//! };
+//! use dias::storage::MemoryStorage;
+//!
+//! async fn load(storage: &impl AsyncStorage) -> std::io::Result<String> {
+//!     storage.data().unwrap().file("level".into()).read_text().await
+//! }
+//!
+//! let mut storage = AsyncAdapter::new(MemoryStorage::new());
+//! pollster::block_on(async {
+//!     let mut file = storage.writable_data().unwrap().writable_file("level".into());
+//!     file.write_text("start").await.unwrap();
+//!     assert_eq!(load(&storage).await.unwrap(), "start");
+//! });
+//! ```
+
+use crate::storage::error::StorageError;
+use crate::storage::generic::{
+    Category, Commit, Entry, File, FileLock, Metadata, ParentDir, Space, Storage, WritableFile,
+    WritableParentDir,
+};
+use std::borrow::Cow;
+use std::future::Future;
+use std::io::{Read, Write};
+
+pub trait AsyncFile {
+    fn exists(&self) -> impl Future<Output = std::io::Result<bool>>;
+    fn metadata(&self) -> impl Future<Output = std::io::Result<Metadata>>;
+    fn read_text(&self) -> impl Future<Output = std::io::Result<String>>;
+    fn read_binary(&self) -> impl Future<Output = std::io::Result<Vec<u8>>>;
+}
+
+pub trait AsyncWritableFile: AsyncFile {
+    fn remove(&mut self) -> impl Future<Output = std::io::Result<()>>;
+    /// Replace the contents of the file.
+    fn write_text(&mut self, text: &str) -> impl Future<Output = std::io::Result<()>>;
+    /// Replace the contents of the file.
+    fn write_binary(&mut self, contents: &[u8]) -> impl Future<Output = std::io::Result<()>>;
+
+    /// See [WritableFile::try_lock]. Never waits, since it fails instead when the file is locked.
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "locking is not supported",
+        ))
+    }
+}
+
+pub trait AsyncDir {
+    type File: AsyncFile;
+    fn file(&self, name: Cow<'static, str>) -> Self::File;
+    fn subdir(&self, name: Cow<'static, str>) -> Self;
+    fn entries(&self) -> impl Future<Output = std::io::Result<Vec<Entry>>>;
+}
+
+pub trait AsyncWritableDir: AsyncDir {
+    type WritableFile: AsyncWritableFile;
+    fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile;
+    fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self;
+    /// Remove everything in this directory.
+    fn clear(&mut self) -> impl Future<Output = std::io::Result<()>>;
+    /// Remove a subdirectory and everything in it.
+    fn remove_subdir(
+        &mut self,
+        name: Cow<'static, str>,
+    ) -> impl Future<Output = std::io::Result<()>>;
+}
+
+pub trait AsyncStorage {
+    type Dir: AsyncDir;
+    type WritableDir: AsyncWritableDir;
+    fn dir(&self, category: Category) -> Result<Self::Dir, StorageError>;
+    fn writable_dir(&mut self, category: Category) -> Result<Self::WritableDir, StorageError>;
+    /// See [Storage::space].
+    fn space(&self) -> impl Future<Output = std::io::Result<Space>>;
+    /// See [Storage::request_persistence].
+    fn request_persistence(&mut self) -> impl Future<Output = std::io::Result<bool>>;
+
+    fn data(&self) -> Result<Self::Dir, StorageError> {
+        self.dir(Category::Data)
+    }
+
+    fn config(&self) -> Result<Self::Dir, StorageError> {
+        self.dir(Category::Config)
+    }
+
+    fn cache(&self) -> Result<Self::Dir, StorageError> {
+        self.dir(Category::Cache)
+    }
+
+    fn writable_data(&mut self) -> Result<Self::WritableDir, StorageError> {
+        self.writable_dir(Category::Data)
+    }
+
+    fn writable_config(&mut self) -> Result<Self::WritableDir, StorageError> {
+        self.writable_dir(Category::Config)
+    }
+
+    fn writable_cache(&mut self) -> Result<Self::WritableDir, StorageError> {
+        self.writable_dir(Category::Cache)
+    }
+}
+
+/// Use synchronous storage, directories or files through the async traits.
+pub struct AsyncAdapter<T> {
+    source: T,
+}
+
+impl<T> From<T> for AsyncAdapter<T> {
+    fn from(source: T) -> Self {
+        Self { source }
+    }
+}
+
+impl<T> AsyncAdapter<T> {
+    pub fn new(source: T) -> Self {
+        Self { source }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.source
+    }
+}
+
+impl<F: File> AsyncFile for AsyncAdapter<F> {
+    async fn exists(&self) -> std::io::Result<bool> {
+        self.source.exists()
+    }
+
+    async fn metadata(&self) -> std::io::Result<Metadata> {
+        self.source.metadata()
+    }
+
+    async fn read_text(&self) -> std::io::Result<String> {
+        let mut text = String::new();
+        self.source.read_text()?.read_to_string(&mut text)?;
+        Ok(text)
+    }
+
+    async fn read_binary(&self) -> std::io::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        self.source.read_binary()?.read_to_end(&mut contents)?;
+        Ok(contents)
+    }
+}
+
+impl<F: WritableFile> AsyncWritableFile for AsyncAdapter<F> {
+    async fn remove(&mut self) -> std::io::Result<()> {
+        self.source.remove()
+    }
+
+    async fn write_text(&mut self, text: &str) -> std::io::Result<()> {
+        let mut write = self.source.write_text()?;
+        write.write_all(text.as_bytes())?;
+        write.commit()
+    }
+
+    async fn write_binary(&mut self, contents: &[u8]) -> std::io::Result<()> {
+        let mut write = self.source.write_binary()?;
+        write.write_all(contents)?;
+        write.commit()
+    }
+
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        self.source.try_lock()
+    }
+}
+
+impl<D: ParentDir> AsyncDir for AsyncAdapter<D> {
+    type File = AsyncAdapter<D::File>;
+
+    fn file(&self, name: Cow<'static, str>) -> Self::File {
+        self.source.file(name).into()
+    }
+
+    fn subdir(&self, name: Cow<'static, str>) -> Self {
+        self.source.subdir(name).into()
+    }
+
+    async fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        self.source.entries()
+    }
+}
+
+impl<D: WritableParentDir> AsyncWritableDir for AsyncAdapter<D> {
+    type WritableFile = AsyncAdapter<D::WritableFile>;
+
+    fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
+        self.source.writable_file(name).into()
+    }
+
+    fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self {
+        self.source.writable_subdir(name).into()
+    }
+
+    async fn clear(&mut self) -> std::io::Result<()> {
+        self.source.clear()
+    }
+
+    async fn remove_subdir(&mut self, name: Cow<'static, str>) -> std::io::Result<()> {
+        self.source.remove_subdir(name)
+    }
+}
+
+impl<S: Storage> AsyncStorage for AsyncAdapter<S> {
+    type Dir = AsyncAdapter<S::Dir>;
+    type WritableDir = AsyncAdapter<S::WritableDir>;
+
+    fn dir(&self, category: Category) -> Result<Self::Dir, StorageError> {
+        self.source.dir(category).map(Into::into)
+    }
+
+    fn writable_dir(&mut self, category: Category) -> Result<Self::WritableDir, StorageError> {
+        self.source.writable_dir(category).map(Into::into)
+    }
+
+    async fn space(&self) -> std::io::Result<Space> {
+        self.source.space().await
+    }
+
+    async fn request_persistence(&mut self) -> std::io::Result<bool> {
+        self.source.request_persistence().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::generic::tests::write_text;
+    use crate::storage::{MemoryStorage, WritableDir};
+
+    async fn use_storage(mut storage: impl AsyncStorage) {
+        let mut dir = storage.writable_data().unwrap();
+        let mut file = dir.writable_subdir("sub".into()).writable_file("a".into());
+        assert!(!file.exists().await.unwrap());
+        file.write_text("hello").await.unwrap();
+        assert!(file.exists().await.unwrap());
+        assert_eq!(file.metadata().await.unwrap().len, 5);
+        file.write_binary(b"\x00\x01").await.unwrap();
+        let data = storage.data().unwrap();
+        let file = data.subdir("sub".into()).file("a".into());
+        assert_eq!(file.read_binary().await.unwrap(), b"\x00\x01");
+        assert_eq!(data.entries().await.unwrap(), vec![Entry::dir("sub")]);
+        dir.writable_subdir("sub".into())
+            .writable_file("b".into())
+            .write_text("b")
+            .await
+            .unwrap();
+        dir.writable_subdir("sub".into())
+            .writable_file("a".into())
+            .remove()
+            .await
+            .unwrap();
+        assert_eq!(
+            data.subdir("sub".into()).entries().await.unwrap(),
+            vec![Entry::file("b")]
+        );
+        dir.remove_subdir("sub".into()).await.unwrap();
+        assert_eq!(data.entries().await.unwrap(), vec![]);
+        dir.writable_file("c".into()).write_text("c").await.unwrap();
+        dir.clear().await.unwrap();
+        assert_eq!(data.entries().await.unwrap(), vec![]);
+        assert!(storage.space().await.unwrap().used.is_some());
+    }
+
+    #[test]
+    fn adapter() {
+        pollster::block_on(use_storage(AsyncAdapter::new(MemoryStorage::new())));
+    }
+
+    #[test]
+    fn read_text() {
+        let mut storage = MemoryStorage::new();
+        write_text(
+            &mut storage
+                .writable_config()
+                .unwrap()
+                .writable_file("settings".into()),
+            b"volume = 5",
+        );
+        let storage = AsyncAdapter::from(storage);
+        let text = pollster::block_on(
+            storage
+                .config()
+                .unwrap()
+                .file("settings".into())
+                .read_text(),
+        );
+        assert_eq!(text.unwrap(), "volume = 5");
+    }
+}
@@ -1,17 +1,19 @@
 //! Support for boxing storage types. Awkward but useful for cases where it is easier to store a
 //! box than use generic types.
 
-use crate::storage::generic::{Dir, File, WritableDir, WritableFile};
+use crate::storage::generic::{
+    Commit, Dir, Entry, File, FileLock, Metadata, Watcher, WritableDir, WritableFile,
+};
 use std::borrow::Cow;
-use std::io::{Read, Write};
+use std::io::Read;
 
 pub type BoxedFile = Box<dyn File<ReadText = Box<dyn Read>, ReadBinary = Box<dyn Read>>>;
 pub type BoxedWritableFile = Box<
     dyn WritableFile<
         ReadText = Box<dyn Read>,
         ReadBinary = Box<dyn Read>,
-        WriteText = Box<dyn Write>,
-        WriteBinary = Box<dyn Write>,
+        WriteText = Box<dyn Commit>,
+        WriteBinary = Box<dyn Commit>,
     >,
 >;
 pub type BoxedDir = Box<dyn Dir<File = BoxedFile>>;
@@ -29,6 +31,14 @@ where
         (**self).exists()
     }
 
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        (**self).metadata()
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        (**self).watch()
+    }
+
     fn read_text(&self) -> std::io::Result<Self::ReadText> {
         (**self).read_text()
     }
@@ -56,6 +66,14 @@ where
     fn write_binary(&mut self) -> std::io::Result<Self::WriteBinary> {
         (**self).write_binary()
     }
+
+    fn location(&self) -> std::io::Result<String> {
+        (**self).location()
+    }
+
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        (**self).try_lock()
+    }
 }
 
 pub struct BoxableFile<F> {
@@ -81,6 +99,14 @@ where
         self.source.exists()
     }
 
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        self.source.metadata()
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.source.watch()
+    }
+
     fn read_text(&self) -> std::io::Result<Self::ReadText> {
         self.source
             .read_text()
@@ -98,12 +124,12 @@ impl<Rt, Rb, Wt, Wb, F> WritableFile for BoxableFile<F>
 where
     Rt: 'static + Read,
     Rb: 'static + Read,
-    Wt: 'static + Write,
-    Wb: 'static + Write,
+    Wt: 'static + Commit,
+    Wb: 'static + Commit,
     F: WritableFile<ReadText = Rt, ReadBinary = Rb, WriteText = Wt, WriteBinary = Wb>,
 {
-    type WriteText = Box<dyn Write>;
-    type WriteBinary = Box<dyn Write>;
+    type WriteText = Box<dyn Commit>;
+    type WriteBinary = Box<dyn Commit>;
 
     fn remove(&mut self) -> std::io::Result<()> {
         self.source.remove()
@@ -112,13 +138,21 @@ where
     fn write_text(&mut self) -> std::io::Result<Self::WriteText> {
         self.source
             .write_text()
-            .map(|w| Box::new(w) as Box<dyn Write>)
+            .map(|w| Box::new(w) as Box<dyn Commit>)
     }
 
     fn write_binary(&mut self) -> std::io::Result<Self::WriteText> {
         self.source
             .write_binary()
-            .map(|w| Box::new(w) as Box<dyn Write>)
+            .map(|w| Box::new(w) as Box<dyn Commit>)
+    }
+
+    fn location(&self) -> std::io::Result<String> {
+        self.source.location()
+    }
+
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        self.source.try_lock()
     }
 }
 
@@ -131,6 +165,14 @@ where
     fn file(&self, name: Cow<'static, str>) -> Self::File {
         (**self).file(name)
     }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        (**self).entries()
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        (**self).watch()
+    }
 }
 
 impl<D> WritableDir for Box<D>
@@ -142,6 +184,10 @@ where
     fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
         (**self).writable_file(name)
     }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        (**self).clear()
+    }
 }
 
 pub struct BoxableDir<D> {
@@ -163,6 +209,14 @@ where
     fn file(&self, name: Cow<'static, str>) -> Self::File {
         Box::new(BoxableFile::from(self.source.file(name)))
     }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        self.source.entries()
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.source.watch()
+    }
 }
 
 impl<D> WritableDir for BoxableDir<D>
@@ -174,6 +228,10 @@ where
     fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
         Box::new(BoxableFile::from(self.source.writable_file(name)))
     }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        self.source.clear()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,167 @@
+use crate::storage::PlatformStorage;
+use crate::AvailabilityError;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Builder for the platform storage, for choosing where storage goes instead of using the
+/// standard locations.
+///
+/// Locations are used in order of priority: the environment variable override if it is set and
+/// not empty, the explicit root, the portable layout, and finally the standard locations found
+/// from the application details. The data, config, cache, state, runtime, logs, saves and
+/// screenshots directories go in `data`, `config`, `cache`, `state`, `runtime`, `logs`, `saves`
+/// and `screenshots` under a root. The portable root is the `storage` directory next to the
+/// executable.
+///
+/// Roots only apply to standard storage, and are ignored on web.
+///
+/// ```rust
+/// use dias::storage::{StorageBuilder, Storage};
+///
+/// let storage = StorageBuilder::new("Bar App")
+///     .organization("Foo Corp")
+///     .qualifier("com")
+///     .env_override("BAR_APP_STORAGE")
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub struct StorageBuilder {
+    application: String,
+    organization: String,
+    qualifier: String,
+    root: Option<PathBuf>,
+    portable: bool,
+    env_override: Option<String>,
+}
+
+impl StorageBuilder {
+    /// See [crate::storage::make_storage] for the application details.
+    pub fn new(application: &str) -> Self {
+        Self {
+            application: application.to_string(),
+            organization: "".to_string(),
+            qualifier: "".to_string(),
+            root: None,
+            portable: false,
+            env_override: None,
+        }
+    }
+
+    pub fn organization(mut self, organization: &str) -> Self {
+        self.organization = organization.to_string();
+        self
+    }
+
+    pub fn qualifier(mut self, qualifier: &str) -> Self {
+        self.qualifier = qualifier.to_string();
+        self
+    }
+
+    /// Put all storage under an explicit directory.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// Put all storage next to the executable, for builds which are run without installing.
+    pub fn portable(mut self, portable: bool) -> Self {
+        self.portable = portable;
+        self
+    }
+
+    /// Put all storage under the directory given by an environment variable, when it is set.
+    pub fn env_override(mut self, var: &str) -> Self {
+        self.env_override = Some(var.to_string());
+        self
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn env_root(&self) -> Option<PathBuf> {
+        let var = self.env_override.as_ref()?;
+        std::env::var_os(var)
+            .filter(|root| !root.is_empty())
+            .map(PathBuf::from)
+    }
+
+    pub fn build(self) -> Result<PlatformStorage, AvailabilityError> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            use crate::storage::standard::Storage;
+            if let Some(root) = self.env_root().or(self.root) {
+                Ok(Storage::with_root(root))
+            } else if self.portable {
+                Storage::portable().map_err(|e| {
+                    AvailabilityError::NotAvailable(Some(Box::new(e) as Box<dyn Error>))
+                })
+            } else {
+                Storage::new(&self.qualifier, &self.organization, &self.application).map_err(|e| {
+                    AvailabilityError::NotAvailable(Some(Box::new(e) as Box<dyn Error>))
+                })
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            crate::storage::web::Storage::new()
+                .map_err(|e| AvailabilityError::NotAvailable(Some(Box::new(e) as Box<dyn Error>)))
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::storage::generic::tests::write_text;
+    use crate::storage::{Category, Storage, WritableDir};
+    use tempfile::tempdir;
+
+    fn write_data(mut storage: PlatformStorage) {
+        write_text(
+            &mut storage
+                .writable_data()
+                .unwrap()
+                .writable_file("test".into()),
+            b"hello",
+        );
+    }
+
+    #[test]
+    fn root() {
+        let temp = tempdir().unwrap();
+        write_data(StorageBuilder::new("a").root(temp.path()).build().unwrap());
+        assert_eq!(
+            std::fs::read_to_string(temp.path().join("data").join("test")).unwrap(),
+            "hello"
+        );
+    }
+
+    #[test]
+    fn env_override() {
+        let temp = tempdir().unwrap();
+        let root = tempdir().unwrap();
+        let var = "DIAS_TEST_STORAGE_BUILDER_ENV_OVERRIDE";
+        std::env::set_var(var, temp.path());
+        let builder = StorageBuilder::new("a").root(root.path()).env_override(var);
+        write_data(builder.clone().build().unwrap());
+        assert!(temp.path().join("data").join("test").exists());
+        assert!(!root.path().join("data").exists());
+        std::env::set_var(var, "");
+        write_data(builder.build().unwrap());
+        assert!(root.path().join("data").join("test").exists());
+        std::env::remove_var(var);
+    }
+
+    #[test]
+    fn portable() {
+        // Only the paths are checked, so nothing is left next to the test executable.
+        let storage = StorageBuilder::new("a").portable(true).build().unwrap();
+        let data = std::env::current_exe()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join("storage")
+            .join("data");
+        assert_eq!(storage.category_path(Category::Data).unwrap(), data);
+    }
+}
@@ -0,0 +1,438 @@
+//! Storage which compresses file contents, wrapping any other storage.
+//!
+//! Compressed contents start with a header, so files written before compression was turned on can
+//! still be read. Text files are stored as binary, so on web they use the denser binary format.
+//! [Metadata] for files gives the compressed size.
+//!
+//! ```rust
+//! use std::io::Write;
+//! use dias::storage::compressed::CompressedStorage;
+//! use dias::storage::{Commit, MemoryStorage, Storage, WritableDir, WritableFile};
+//!
+//! let mut storage = CompressedStorage::new(MemoryStorage::new());
+//! let mut file = storage.writable_data().unwrap().writable_file("save.json".into());
+//! let mut write = file.write_text().unwrap();
+//! write!(write, "{{\"level\": 1}}").unwrap();
+//! write.commit().unwrap();
+//! ```
+
+use crate::storage::error::StorageError;
+use crate::storage::generic::{
+    finished_writer, Category, Commit, Dir, Entry, File, FileLock, Metadata, ParentDir, Space,
+    Storage, Watcher, WritableDir, WritableFile, WritableParentDir,
+};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+/// Start of compressed contents. The first byte never appears in UTF-8 text.
+static HEADER: &[u8] = b"\xFFdz\x01";
+
+/// Check whether contents start with the header, consuming it if so.
+fn read_header(read: &mut impl Read) -> std::io::Result<bool> {
+    let mut header = [0; HEADER.len()];
+    let mut len = 0;
+    while len < header.len() {
+        match read.read(&mut header[len..])? {
+            0 => return Ok(false),
+            n => len += n,
+        }
+    }
+    Ok(header == HEADER)
+}
+
+pub enum CompressedRead<C, P> {
+    Compressed(ZlibDecoder<C>),
+    Plain(P),
+}
+
+impl<C: Read, P: Read> Read for CompressedRead<C, P> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Compressed(read) => read.read(buf).map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::InvalidInput => {
+                    StorageError::corrupt(e).into()
+                }
+                _ => e,
+            }),
+            Self::Plain(read) => read.read(buf),
+        }
+    }
+}
+
+pub struct CompressedWrite<W: Write> {
+    write: Option<ZlibEncoder<W>>,
+}
+
+impl<W: Write> CompressedWrite<W> {
+    fn new(mut write: W) -> std::io::Result<Self> {
+        write.write_all(HEADER)?;
+        Ok(Self {
+            write: Some(ZlibEncoder::new(write, Compression::default())),
+        })
+    }
+
+    fn write(&mut self) -> std::io::Result<&mut ZlibEncoder<W>> {
+        self.write.as_mut().ok_or_else(finished_writer)
+    }
+}
+
+impl<W: Write> Write for CompressedWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write()?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write()?.flush()
+    }
+}
+
+impl<W: Commit> Commit for CompressedWrite<W> {
+    fn commit(&mut self) -> std::io::Result<()> {
+        let write = self.write.take().ok_or_else(finished_writer)?;
+        write.finish()?.commit()
+    }
+
+    fn abort(&mut self) {
+        if let Some(mut write) = self.write.take() {
+            write.get_mut().abort();
+        }
+    }
+}
+
+pub struct CompressedFile<F> {
+    source: F,
+}
+
+impl<F> From<F> for CompressedFile<F> {
+    fn from(source: F) -> Self {
+        Self { source }
+    }
+}
+
+impl<F: File> CompressedFile<F> {
+    fn read<P: Read>(
+        &self,
+        read_plain: impl FnOnce(&F) -> std::io::Result<P>,
+    ) -> std::io::Result<CompressedRead<F::ReadBinary, P>> {
+        let mut read = self.source.read_binary()?;
+        if read_header(&mut read)? {
+            Ok(CompressedRead::Compressed(ZlibDecoder::new(read)))
+        } else {
+            Ok(CompressedRead::Plain(read_plain(&self.source)?))
+        }
+    }
+}
+
+impl<F: File> File for CompressedFile<F> {
+    type ReadText = CompressedRead<F::ReadBinary, F::ReadText>;
+    type ReadBinary = CompressedRead<F::ReadBinary, F::ReadBinary>;
+
+    fn exists(&self) -> std::io::Result<bool> {
+        self.source.exists()
+    }
+
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        self.source.metadata()
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.source.watch()
+    }
+
+    fn read_text(&self) -> std::io::Result<Self::ReadText> {
+        self.read(F::read_text)
+    }
+
+    fn read_binary(&self) -> std::io::Result<Self::ReadBinary> {
+        self.read(F::read_binary)
+    }
+}
+
+impl<F: WritableFile> WritableFile for CompressedFile<F> {
+    type WriteText = CompressedWrite<F::WriteBinary>;
+    type WriteBinary = CompressedWrite<F::WriteBinary>;
+
+    fn remove(&mut self) -> std::io::Result<()> {
+        self.source.remove()
+    }
+
+    fn write_text(&mut self) -> std::io::Result<Self::WriteText> {
+        CompressedWrite::new(self.source.write_binary()?)
+    }
+
+    fn write_binary(&mut self) -> std::io::Result<Self::WriteBinary> {
+        CompressedWrite::new(self.source.write_binary()?)
+    }
+
+    fn location(&self) -> std::io::Result<String> {
+        self.source.location()
+    }
+
+    fn copy_to(&self, target: &mut Self) -> std::io::Result<()> {
+        self.source.copy_to(&mut target.source)
+    }
+
+    fn move_to(&mut self, target: &mut Self) -> std::io::Result<()> {
+        self.source.move_to(&mut target.source)
+    }
+
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        self.source.try_lock()
+    }
+}
+
+pub struct CompressedDir<D> {
+    source: D,
+}
+
+impl<D> From<D> for CompressedDir<D> {
+    fn from(source: D) -> Self {
+        Self { source }
+    }
+}
+
+impl<D: Dir> Dir for CompressedDir<D> {
+    type File = CompressedFile<D::File>;
+
+    fn file(&self, name: Cow<'static, str>) -> Self::File {
+        self.source.file(name).into()
+    }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        self.source.entries()
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.source.watch()
+    }
+}
+
+impl<D: WritableDir> WritableDir for CompressedDir<D> {
+    type WritableFile = CompressedFile<D::WritableFile>;
+
+    fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
+        self.source.writable_file(name).into()
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        self.source.clear()
+    }
+}
+
+impl<D: ParentDir> ParentDir for CompressedDir<D> {
+    type LeafDir = CompressedDir<D::LeafDir>;
+
+    fn subdir(&self, name: Cow<'static, str>) -> Self {
+        self.source.subdir(name).into()
+    }
+
+    fn into_leaf(self) -> Self::LeafDir {
+        self.source.into_leaf().into()
+    }
+}
+
+impl<D: WritableParentDir> WritableParentDir for CompressedDir<D> {
+    type WritableLeafDir = CompressedDir<D::WritableLeafDir>;
+
+    fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self {
+        self.source.writable_subdir(name).into()
+    }
+
+    fn into_writable_leaf(self) -> Self::WritableLeafDir {
+        self.source.into_writable_leaf().into()
+    }
+
+    fn remove_subdir(&mut self, name: Cow<'static, str>) -> std::io::Result<()> {
+        self.source.remove_subdir(name)
+    }
+}
+
+pub struct CompressedStorage<S> {
+    source: S,
+}
+
+impl<S: Storage> CompressedStorage<S> {
+    pub fn new(source: S) -> Self {
+        Self { source }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S: Storage> Storage for CompressedStorage<S> {
+    type Dir = CompressedDir<S::Dir>;
+    type WritableDir = CompressedDir<S::WritableDir>;
+
+    fn dir(&self, category: Category) -> Result<Self::Dir, StorageError> {
+        self.source.dir(category).map(Into::into)
+    }
+
+    fn writable_dir(&mut self, category: Category) -> Result<Self::WritableDir, StorageError> {
+        self.source.writable_dir(category).map(Into::into)
+    }
+
+    async fn space(&self) -> std::io::Result<Space> {
+        self.source.space().await
+    }
+
+    async fn request_persistence(&mut self) -> std::io::Result<bool> {
+        self.source.request_persistence().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::generic::tests as generic_tests;
+    use super::*;
+    use crate::storage::boxable::{BoxableDir, BoxedWritableDir};
+    use crate::storage::MemoryStorage;
+
+    fn make_storage() -> CompressedStorage<MemoryStorage> {
+        CompressedStorage::new(MemoryStorage::new())
+    }
+
+    #[test]
+    fn text_file() {
+        generic_tests::text_file(make_storage());
+    }
+
+    #[test]
+    fn binary_file() {
+        generic_tests::binary_file(make_storage());
+    }
+
+    #[test]
+    fn missing_file() {
+        generic_tests::missing_file(make_storage());
+    }
+
+    #[test]
+    fn commit_and_abort() {
+        generic_tests::commit_and_abort(make_storage());
+    }
+
+    #[test]
+    fn copy_and_move() {
+        generic_tests::copy_and_move(make_storage());
+    }
+
+    #[test]
+    fn listing() {
+        generic_tests::listing(make_storage());
+    }
+
+    #[test]
+    fn locking() {
+        generic_tests::locking(make_storage());
+    }
+
+    #[test]
+    fn compresses() {
+        let mut storage = make_storage();
+        let text = "{\"score\": 0}\n".repeat(100);
+        let mut file = storage
+            .writable_data()
+            .unwrap()
+            .writable_file("save".into());
+        generic_tests::write_text(&mut file, text.as_bytes());
+        assert!(file.metadata().unwrap().len < text.len() as u64 / 10);
+        let mut got = String::new();
+        file.read_text().unwrap().read_to_string(&mut got).unwrap();
+        assert_eq!(got, text);
+    }
+
+    #[test]
+    fn reads_uncompressed() {
+        let mut storage = make_storage();
+        for contents in [&b"plain"[..], b"", b"\xFF", b"\xFFdz\x02 other"] {
+            generic_tests::write_binary(
+                &mut storage
+                    .source
+                    .writable_data()
+                    .unwrap()
+                    .writable_file("old".into()),
+                contents,
+            );
+            let file = storage.data().unwrap().file("old".into());
+            let mut got = Vec::new();
+            file.read_binary().unwrap().read_to_end(&mut got).unwrap();
+            assert_eq!(got, contents);
+        }
+    }
+
+    #[test]
+    fn readable_after_commit() {
+        let mut storage = make_storage();
+        let mut file = storage
+            .writable_data()
+            .unwrap()
+            .writable_file("partial".into());
+        let mut write = file.write_binary().unwrap();
+        write.write_all(b"hello").unwrap();
+        write.flush().unwrap();
+        assert!(!file.exists().unwrap());
+        write.commit().unwrap();
+        let mut got = Vec::new();
+        file.read_binary().unwrap().read_to_end(&mut got).unwrap();
+        assert_eq!(got, b"hello");
+    }
+
+    #[test]
+    fn truncated() {
+        let mut storage = make_storage();
+        let mut file = storage
+            .writable_data()
+            .unwrap()
+            .writable_file("save".into());
+        generic_tests::write_text(&mut file, "{\"score\": 0}\n".repeat(100).as_bytes());
+        let mut raw = Vec::new();
+        storage
+            .source
+            .data()
+            .unwrap()
+            .file("save".into())
+            .read_binary()
+            .unwrap()
+            .read_to_end(&mut raw)
+            .unwrap();
+        raw.truncate(raw.len() - 4);
+        generic_tests::write_binary(
+            &mut storage
+                .source
+                .writable_data()
+                .unwrap()
+                .writable_file("save".into()),
+            &raw,
+        );
+        let error = file
+            .read_text()
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(matches!(
+            StorageError::from(error),
+            StorageError::Corrupt(_)
+        ));
+    }
+
+    #[test]
+    fn boxable() {
+        let mut storage = make_storage();
+        let mut dir: BoxedWritableDir =
+            Box::new(BoxableDir::from(storage.writable_data().unwrap()));
+        generic_tests::write_text(&mut dir.writable_file("boxed".into()), b"boxed");
+        let mut got = String::new();
+        dir.file("boxed".into())
+            .read_text()
+            .unwrap()
+            .read_to_string(&mut got)
+            .unwrap();
+        assert_eq!(got, "boxed");
+    }
+}
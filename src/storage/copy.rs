@@ -0,0 +1,354 @@
+//! Copying whole directory trees or whole storage from one backend to another, such as when
+//! moving to a different web backend or importing desktop saves into a web build.
+//!
+//! Files which are already in the target are handled as chosen by [Existing]. Mirroring also
+//! removes files from the target which are not in the source. The [CopyReport] lists what was
+//! done, with paths separated by `/` from the top of the copy.
+//!
+//! ```rust
+//! use dias::storage::copy::{copy_storage, CopyOptions, Existing};
+//! use dias::storage::{MemorySnapshot, MemoryStorage};
+//!
+//! let desktop = MemoryStorage::from_snapshot(MemorySnapshot::from_iter([
+//!     ("data/saves/1", "level 3"),
+//!     ("config/settings", "volume = 5"),
+//! ]))
+//! .unwrap();
+//! let mut web = MemoryStorage::new();
+//! let options = CopyOptions::new().existing(Existing::Newer);
+//! let report = copy_storage(&desktop, &mut web, &options).unwrap();
+//! assert_eq!(report.copied, vec!["data/saves/1", "config/settings"]);
+//! assert_eq!(web.snapshot().unwrap(), desktop.snapshot().unwrap());
+//! ```
+
+use crate::storage::error::StorageError;
+use crate::storage::generic::{
+    Category, Commit, EntryKind, File, ParentDir, Storage, WritableFile, WritableParentDir,
+};
+
+/// What to do with files which are already in the target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Existing {
+    /// Replace them.
+    #[default]
+    Overwrite,
+    /// Keep them.
+    Skip,
+    /// Replace them only if the source was modified later. Files whose modification times are not
+    /// known are replaced.
+    Newer,
+}
+
+#[derive(Debug, Clone)]
+pub struct CopyOptions {
+    existing: Existing,
+    mirror: bool,
+    categories: Vec<Category>,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CopyOptions {
+    pub fn new() -> Self {
+        Self {
+            existing: Existing::default(),
+            mirror: false,
+            categories: Category::ALL.to_vec(),
+        }
+    }
+
+    pub fn existing(mut self, existing: Existing) -> Self {
+        self.existing = existing;
+        self
+    }
+
+    /// Remove files and directories from the target which are not in the source.
+    pub fn mirror(mut self, mirror: bool) -> Self {
+        self.mirror = mirror;
+        self
+    }
+
+    /// Categories to copy with [copy_storage]. All categories are copied by default.
+    pub fn categories(mut self, categories: &[Category]) -> Self {
+        self.categories = categories.to_vec();
+        self
+    }
+}
+
+/// What was done by a copy.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CopyReport {
+    /// Files written to the target.
+    pub copied: Vec<String>,
+    /// Files left alone because they were already in the target.
+    pub skipped: Vec<String>,
+    /// Files and directories removed from the target when mirroring.
+    pub removed: Vec<String>,
+    /// Categories left alone by [copy_storage] because the source does not have them.
+    pub skipped_categories: Vec<Category>,
+    /// Total size of the files copied.
+    pub bytes: u64,
+}
+
+impl CopyReport {
+    fn extend(&mut self, prefix: &str, other: CopyReport) {
+        let add_prefix = |paths: Vec<String>| paths.into_iter().map(|p| format!("{prefix}/{p}"));
+        self.copied.extend(add_prefix(other.copied));
+        self.skipped.extend(add_prefix(other.skipped));
+        self.removed.extend(add_prefix(other.removed));
+        self.skipped_categories.extend(other.skipped_categories);
+        self.bytes += other.bytes;
+    }
+}
+
+fn should_copy(
+    source: &impl File,
+    target: &impl File,
+    existing: Existing,
+) -> std::io::Result<bool> {
+    if !target.exists()? {
+        return Ok(true);
+    }
+    Ok(match existing {
+        Existing::Overwrite => true,
+        Existing::Skip => false,
+        Existing::Newer => match (source.metadata()?.modified, target.metadata()?.modified) {
+            (Some(source), Some(target)) => source > target,
+            _ => true,
+        },
+    })
+}
+
+/// Copy the contents of a file as binary, which every backend reads back the same whether the
+/// file was written as text or binary.
+fn copy_file(source: &impl File, target: &mut impl WritableFile) -> std::io::Result<u64> {
+    let mut write = target.write_binary()?;
+    let len = std::io::copy(&mut source.read_binary()?, &mut write)?;
+    write.commit()?;
+    Ok(len)
+}
+
+/// Copy everything in a directory and its subdirectories into another directory, which can be
+/// from a different backend.
+pub fn copy_dir<S: ParentDir, T: WritableParentDir>(
+    source: &S,
+    target: &mut T,
+    options: &CopyOptions,
+) -> std::io::Result<CopyReport> {
+    let mut report = CopyReport::default();
+    let entries = source.entries()?;
+    if options.mirror {
+        for entry in target.entries()? {
+            if entries.contains(&entry) {
+                continue;
+            }
+            match entry.kind {
+                EntryKind::File => target.writable_file(entry.name.clone().into()).remove()?,
+                EntryKind::Dir => target.remove_subdir(entry.name.clone().into())?,
+            }
+            report.removed.push(entry.name);
+        }
+    }
+    for entry in entries {
+        match entry.kind {
+            EntryKind::File => {
+                let source = source.file(entry.name.clone().into());
+                let mut target = target.writable_file(entry.name.clone().into());
+                if should_copy(&source, &target, options.existing)? {
+                    report.bytes += copy_file(&source, &mut target)?;
+                    report.copied.push(entry.name);
+                } else {
+                    report.skipped.push(entry.name);
+                }
+            }
+            EntryKind::Dir => {
+                let sub_report = copy_dir(
+                    &source.subdir(entry.name.clone().into()),
+                    &mut target.writable_subdir(entry.name.clone().into()),
+                    options,
+                )?;
+                report.extend(&entry.name, sub_report);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Copy the categories chosen in the options from one storage to another, which can be a
+/// different backend. Categories which the source does not have, so which are
+/// [StorageError::Unsupported], are left alone and listed in the report. Paths in the report start
+/// with the category name.
+pub fn copy_storage(
+    source: &impl Storage,
+    target: &mut impl Storage,
+    options: &CopyOptions,
+) -> std::io::Result<CopyReport> {
+    let mut report = CopyReport::default();
+    for category in &options.categories {
+        let source = match source.dir(*category) {
+            Ok(source) => source,
+            Err(StorageError::Unsupported) => {
+                report.skipped_categories.push(*category);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut target = target.writable_dir(*category)?;
+        report.extend(category.name(), copy_dir(&source, &mut target, options)?);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::embedded::EmbeddedStorage;
+    use crate::storage::generic::tests::write_text;
+    use crate::storage::{MemorySnapshot, MemoryStorage, WritableDir};
+
+    fn source() -> MemoryStorage {
+        MemoryStorage::from_snapshot(MemorySnapshot::from_iter([
+            ("data/a", "new a"),
+            ("data/sub/b", "new b"),
+            ("config/c", "new c"),
+        ]))
+        .unwrap()
+    }
+
+    fn target() -> MemoryStorage {
+        MemoryStorage::from_snapshot(MemorySnapshot::from_iter([
+            ("data/a", "old a"),
+            ("data/old/d", "old d"),
+            ("data/sub/e", "old e"),
+            ("cache/f", "old f"),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn overwrite() {
+        let mut target = target();
+        let report = copy_storage(&source(), &mut target, &CopyOptions::new()).unwrap();
+        assert_eq!(report.copied, vec!["data/a", "data/sub/b", "config/c"]);
+        assert_eq!(report.skipped, Vec::<String>::new());
+        assert_eq!(report.bytes, 15);
+        assert_eq!(
+            target.snapshot().unwrap(),
+            MemorySnapshot::from_iter([
+                ("data/a", "new a"),
+                ("data/old/d", "old d"),
+                ("data/sub/b", "new b"),
+                ("data/sub/e", "old e"),
+                ("cache/f", "old f"),
+                ("config/c", "new c"),
+            ])
+        );
+    }
+
+    #[test]
+    fn skip() {
+        let mut target = target();
+        let options = CopyOptions::new()
+            .existing(Existing::Skip)
+            .categories(&[Category::Data]);
+        let report = copy_storage(&source(), &mut target, &options).unwrap();
+        assert_eq!(report.copied, vec!["data/sub/b"]);
+        assert_eq!(report.skipped, vec!["data/a"]);
+        assert_eq!(
+            target.snapshot().unwrap().files["data/a"],
+            b"old a".to_vec()
+        );
+    }
+
+    #[test]
+    fn newer() {
+        let mut target = target();
+        let source = source();
+        write_text(
+            &mut target.writable_data().unwrap().writable_file("a".into()),
+            b"newest a",
+        );
+        let options = CopyOptions::new().existing(Existing::Newer);
+        let report = copy_storage(&source, &mut target, &options).unwrap();
+        assert_eq!(report.copied, vec!["data/sub/b", "config/c"]);
+        assert_eq!(report.skipped, vec!["data/a"]);
+        let report = copy_storage(&target, &mut MemoryStorage::new(), &options).unwrap();
+        assert_eq!(report.copied.len(), 6);
+    }
+
+    #[test]
+    fn mirror() {
+        let mut target = target();
+        let options = CopyOptions::new().mirror(true);
+        let report = copy_storage(&source(), &mut target, &options).unwrap();
+        assert_eq!(report.removed, vec!["data/old", "data/sub/e", "cache/f"]);
+        assert_eq!(target.snapshot().unwrap(), source().snapshot().unwrap());
+        let report = copy_storage(&source(), &mut target, &options).unwrap();
+        assert_eq!(report.removed, Vec::<String>::new());
+    }
+
+    #[test]
+    fn dirs() {
+        let source = source();
+        let mut target = MemoryStorage::new();
+        let report = copy_dir(
+            &source.data().unwrap().subdir("sub".into()),
+            &mut target.writable_cache().unwrap(),
+            &CopyOptions::new(),
+        )
+        .unwrap();
+        assert_eq!(report.copied, vec!["b"]);
+        assert_eq!(
+            target.snapshot().unwrap(),
+            MemorySnapshot::from_iter([("cache/b", "new b")])
+        );
+    }
+
+    #[test]
+    fn missing_categories() {
+        static TABLE: crate::storage::embedded::Table = &[("a", b"a")];
+        let mut target = MemoryStorage::new();
+        let report = copy_storage(
+            &EmbeddedStorage::new(TABLE),
+            &mut target,
+            &CopyOptions::new(),
+        )
+        .unwrap();
+        assert_eq!(report.copied, vec!["data/a"]);
+        assert_eq!(report.skipped_categories, &Category::ALL[1..]);
+        let error = copy_storage(
+            &target,
+            &mut EmbeddedStorage::new(TABLE),
+            &CopyOptions::new(),
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn failing_category() {
+        use crate::storage::faulty::{FaultyStorage, Operation};
+        let source = FaultyStorage::new(source());
+        source.faults().unavailable(Category::Config, true);
+        let error =
+            copy_storage(&source, &mut MemoryStorage::new(), &CopyOptions::new()).unwrap_err();
+        assert!(matches!(
+            StorageError::from(error),
+            StorageError::BackendUnavailable(_)
+        ));
+        source.faults().clear();
+        for kind in [
+            std::io::ErrorKind::PermissionDenied,
+            std::io::ErrorKind::Other,
+        ] {
+            source.faults().fail_after(Operation::Dir, 1, kind);
+            let error =
+                copy_storage(&source, &mut MemoryStorage::new(), &CopyOptions::new()).unwrap_err();
+            assert_eq!(error.kind(), kind);
+        }
+    }
+}
@@ -0,0 +1,456 @@
+//! Read-only storage compiled into the binary, for bundling assets into single file builds.
+//!
+//! Contents are a static table of paths and file contents, with `/` separating directories. A
+//! table can be written by hand or generated from a directory by a build script with
+//! [generate_table]:
+//!
+//! ```rust,ignore
+//! // build.rs, with dias as a build dependency
+//! fn main() {
+//!     let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("assets.rs");
+//!     dias::storage::embedded::generate_table("assets", out).unwrap();
+//! }
+//! ```
+//!
+//! ```rust,ignore
+//! static ASSETS: dias::storage::embedded::Table = include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+//! ```
+//!
+//! The table is then read through the usual traits:
+//!
+//! ```rust
+//! use std::io::Read;
+//! use dias::storage::embedded::{EmbeddedDir, Table};
+//! use dias::storage::{Dir, File, ParentDir};
+//!
+//! static ASSETS: Table = &[("levels/1.txt", b"start")];
+//!
+//! let mut text = String::new();
+//! EmbeddedDir::new(ASSETS)
+//!     .subdir("levels".into())
+//!     .file("1.txt".into())
+//!     .read_text()
+//!     .unwrap()
+//!     .read_to_string(&mut text)
+//!     .unwrap();
+//! assert_eq!(text, "start");
+//! ```
+//!
+//! Writable files and directories exist only to fill in the storage traits, and every write fails
+//! with [std::io::ErrorKind::PermissionDenied].
+
+use crate::storage::error::StorageError;
+use crate::storage::generic::{
+    Category, Commit, Dir, Entry, File, Metadata, ParentDir, Space, Storage, Watcher, WritableDir,
+    WritableFile, WritableParentDir,
+};
+use crate::storage::keys::{entries_under, join};
+use crate::storage::name::InvalidNameError;
+use std::borrow::Cow;
+use std::io::{Cursor, Write};
+
+static SEP: char = '/';
+
+/// Paths and contents of embedded files.
+pub type Table = &'static [(&'static str, &'static [u8])];
+
+fn read_only() -> std::io::Error {
+    StorageError::PermissionDenied.into()
+}
+
+/// Writer for embedded files, which can never be made because they are read-only.
+pub enum NoWrite {}
+
+impl Write for NoWrite {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        match *self {}
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match *self {}
+    }
+}
+
+impl Commit for NoWrite {
+    fn commit(&mut self) -> std::io::Result<()> {
+        match *self {}
+    }
+
+    fn abort(&mut self) {
+        match *self {}
+    }
+}
+
+pub struct EmbeddedFile {
+    table: Table,
+    path: Result<String, InvalidNameError>,
+}
+
+impl EmbeddedFile {
+    fn path(&self) -> std::io::Result<&String> {
+        self.path.as_ref().map_err(|e| e.clone().into())
+    }
+
+    fn contents(&self) -> std::io::Result<&'static [u8]> {
+        let path = self.path()?;
+        self.table
+            .iter()
+            .find(|(key, _)| key == path)
+            .map(|(_, contents)| *contents)
+            .ok_or_else(|| StorageError::NotFound.into())
+    }
+}
+
+impl File for EmbeddedFile {
+    type ReadText = Cursor<&'static [u8]>;
+    type ReadBinary = Cursor<&'static [u8]>;
+
+    fn exists(&self) -> std::io::Result<bool> {
+        let path = self.path()?;
+        Ok(self.table.iter().any(|(key, _)| key == path))
+    }
+
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        Ok(Metadata {
+            len: self.contents()?.len() as u64,
+            modified: None,
+        })
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.path()?;
+        Ok(Watcher::new(|| Ok(Vec::new())))
+    }
+
+    fn read_text(&self) -> std::io::Result<Self::ReadText> {
+        self.contents().map(Cursor::new)
+    }
+
+    fn read_binary(&self) -> std::io::Result<Self::ReadBinary> {
+        self.contents().map(Cursor::new)
+    }
+}
+
+impl WritableFile for EmbeddedFile {
+    type WriteText = NoWrite;
+    type WriteBinary = NoWrite;
+
+    fn remove(&mut self) -> std::io::Result<()> {
+        Err(read_only())
+    }
+
+    fn write_text(&mut self) -> std::io::Result<Self::WriteText> {
+        Err(read_only())
+    }
+
+    fn write_binary(&mut self) -> std::io::Result<Self::WriteBinary> {
+        Err(read_only())
+    }
+
+    fn location(&self) -> std::io::Result<String> {
+        self.path().cloned()
+    }
+
+    fn copy_to(&self, _target: &mut Self) -> std::io::Result<()> {
+        Err(read_only())
+    }
+
+    fn move_to(&mut self, _target: &mut Self) -> std::io::Result<()> {
+        Err(read_only())
+    }
+}
+
+pub struct EmbeddedDir {
+    table: Table,
+    path: Result<String, InvalidNameError>,
+}
+
+impl EmbeddedDir {
+    /// The root directory of a table.
+    pub fn new(table: Table) -> Self {
+        Self {
+            table,
+            path: Ok("".to_string()),
+        }
+    }
+
+    fn path(&self) -> std::io::Result<&String> {
+        self.path.as_ref().map_err(|e| e.clone().into())
+    }
+}
+
+impl Dir for EmbeddedDir {
+    type File = EmbeddedFile;
+
+    fn file(&self, name: Cow<'static, str>) -> Self::File {
+        EmbeddedFile {
+            table: self.table,
+            path: join(&self.path, &name),
+        }
+    }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        let keys = self.table.iter().map(|(key, _)| *key);
+        Ok(entries_under(self.path()?, SEP, keys))
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.path()?;
+        Ok(Watcher::new(|| Ok(Vec::new())))
+    }
+}
+
+impl WritableDir for EmbeddedDir {
+    type WritableFile = EmbeddedFile;
+
+    fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
+        self.file(name)
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        Err(read_only())
+    }
+
+    fn rename(&mut self, _from: Cow<'static, str>, _to: Cow<'static, str>) -> std::io::Result<()> {
+        Err(read_only())
+    }
+}
+
+impl ParentDir for EmbeddedDir {
+    type LeafDir = Self;
+
+    fn subdir(&self, name: Cow<'static, str>) -> Self {
+        let path = join(&self.path, &name).map(|mut path| {
+            path.push(SEP);
+            path
+        });
+        Self {
+            table: self.table,
+            path,
+        }
+    }
+
+    fn into_leaf(self) -> Self::LeafDir {
+        self
+    }
+}
+
+impl WritableParentDir for EmbeddedDir {
+    type WritableLeafDir = Self;
+
+    fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self {
+        self.subdir(name)
+    }
+
+    fn into_writable_leaf(self) -> Self::WritableLeafDir {
+        self
+    }
+
+    fn remove_subdir(&mut self, _name: Cow<'static, str>) -> std::io::Result<()> {
+        Err(read_only())
+    }
+}
+
+/// Storage with an embedded table as its data directory. There are no config or cache
+/// directories, and none of the directories are writable.
+pub struct EmbeddedStorage {
+    table: Table,
+}
+
+impl EmbeddedStorage {
+    pub fn new(table: Table) -> Self {
+        Self { table }
+    }
+}
+
+impl Storage for EmbeddedStorage {
+    type Dir = EmbeddedDir;
+    type WritableDir = EmbeddedDir;
+
+    fn dir(&self, category: Category) -> Result<Self::Dir, StorageError> {
+        match category {
+            Category::Data => Ok(EmbeddedDir::new(self.table)),
+            _ => Err(StorageError::Unsupported),
+        }
+    }
+
+    fn writable_dir(&mut self, _category: Category) -> Result<Self::WritableDir, StorageError> {
+        Err(StorageError::PermissionDenied)
+    }
+
+    async fn space(&self) -> std::io::Result<Space> {
+        let used = self
+            .table
+            .iter()
+            .map(|(_, contents)| contents.len() as u64)
+            .sum();
+        Ok(Space {
+            used: Some(used),
+            available: Some(0),
+        })
+    }
+
+    async fn request_persistence(&mut self) -> std::io::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Write a Rust expression for a [Table] of every file under `dir` to `out`, for use from a build
+/// script. Files are included with [include_bytes], and the build script is rerun when anything
+/// under `dir` changes. Fails with [std::io::ErrorKind::InvalidInput] if a file or directory name
+/// is not a valid storage name.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn generate_table(
+    dir: impl AsRef<std::path::Path>,
+    out: impl AsRef<std::path::Path>,
+) -> std::io::Result<()> {
+    use std::fmt::Write as _;
+
+    fn collect(
+        dir: &std::path::Path,
+        prefix: &str,
+        found: &mut Vec<(String, std::path::PathBuf)>,
+    ) -> std::io::Result<()> {
+        println!("cargo:rerun-if-changed={}", dir.display());
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().into_string().map_err(|name| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("file name is not UTF-8: {:?}", name),
+                )
+            })?;
+            crate::storage::validate_name(&name)?;
+            let key = format!("{}{}", prefix, name);
+            if entry.file_type()?.is_dir() {
+                collect(&entry.path(), &format!("{}{}", key, SEP), found)?;
+            } else {
+                println!("cargo:rerun-if-changed={}", entry.path().display());
+                found.push((key, entry.path()));
+            }
+        }
+        Ok(())
+    }
+
+    let mut found = Vec::new();
+    collect(&std::fs::canonicalize(dir)?, "", &mut found)?;
+    found.sort();
+    let mut table = "&[\n".to_string();
+    for (key, path) in found {
+        let path = path.to_str().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("path is not UTF-8: {}", path.display()),
+            )
+        })?;
+        let _ = writeln!(table, "    ({:?}, include_bytes!({:?})),", key, path);
+    }
+    table.push_str("]\n");
+    std::fs::write(out, table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    static TABLE: Table = &[
+        ("readme.txt", b"hello"),
+        ("levels/1.txt", b"one"),
+        ("levels/2.txt", b"two"),
+        ("levels/extra/3.txt", b"three"),
+    ];
+
+    fn read(file: &EmbeddedFile) -> String {
+        let mut got = String::new();
+        file.read_text().unwrap().read_to_string(&mut got).unwrap();
+        got
+    }
+
+    #[test]
+    fn reading() {
+        let storage = EmbeddedStorage::new(TABLE);
+        let data = storage.data().unwrap();
+        assert_eq!(read(&data.file("readme.txt".into())), "hello");
+        let levels = data.subdir("levels".into());
+        assert_eq!(read(&levels.file("2.txt".into())), "two");
+        assert_eq!(
+            read(&levels.subdir("extra".into()).file("3.txt".into())),
+            "three"
+        );
+        assert_eq!(levels.file("1.txt".into()).metadata().unwrap().len, 3);
+        assert!(!levels.file("4.txt".into()).exists().unwrap());
+        assert_eq!(
+            levels
+                .file("4.txt".into())
+                .read_text()
+                .err()
+                .unwrap()
+                .kind(),
+            std::io::ErrorKind::NotFound
+        );
+        assert_eq!(
+            data.file("../readme.txt".into())
+                .exists()
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn listing() {
+        let data = EmbeddedDir::new(TABLE);
+        assert_eq!(
+            data.entries().unwrap(),
+            vec![Entry::dir("levels"), Entry::file("readme.txt")]
+        );
+        assert_eq!(
+            data.subdir("levels".into()).entries().unwrap(),
+            vec![
+                Entry::file("1.txt"),
+                Entry::file("2.txt"),
+                Entry::dir("extra")
+            ]
+        );
+        assert_eq!(data.subdir("missing".into()).entries().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn writing_fails() {
+        let mut storage = EmbeddedStorage::new(TABLE);
+        assert!(storage.writable_data().is_err());
+        let mut data = EmbeddedDir::new(TABLE);
+        let mut file = data.writable_file("readme.txt".into());
+        assert_eq!(
+            file.write_text().err().unwrap().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            file.remove().unwrap_err().kind(),
+            std::io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(read(&file), "hello");
+        let space = pollster::block_on(storage.space()).unwrap();
+        assert_eq!(space.used, Some(16));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn generating() {
+        let dir = tempfile::tempdir().unwrap();
+        let assets = dir.path().join("assets");
+        std::fs::create_dir_all(assets.join("levels")).unwrap();
+        std::fs::write(assets.join("readme.txt"), "hello").unwrap();
+        std::fs::write(assets.join("levels").join("1.txt"), "one").unwrap();
+        let out = dir.path().join("table.rs");
+        generate_table(&assets, &out).unwrap();
+        let assets = std::fs::canonicalize(&assets).unwrap();
+        let expected = format!(
+            "&[\n    (\"levels/1.txt\", include_bytes!({:?})),\n    (\"readme.txt\", include_bytes!({:?})),\n]\n",
+            assets.join("levels").join("1.txt").to_str().unwrap(),
+            assets.join("readme.txt").to_str().unwrap(),
+        );
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), expected);
+    }
+}
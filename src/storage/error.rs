@@ -0,0 +1,197 @@
+//! Errors from storage. Every backend gives the same [StorageError] for the same problem, wrapped
+//! in a [std::io::Error] of the matching kind where the storage traits return those.
+
+use crate::storage::name::InvalidNameError;
+use std::error::Error;
+use std::fmt;
+use std::sync::PoisonError;
+
+type Source = Box<dyn Error + Send + Sync>;
+
+#[derive(Debug)]
+pub enum StorageError {
+    /// The file does not exist.
+    NotFound,
+    /// There is no space left for storage.
+    QuotaExceeded,
+    InvalidName(InvalidNameError),
+    PermissionDenied,
+    /// Stored contents can not be understood.
+    Corrupt(Option<Source>),
+    /// The backend or the directory can not be used, such as when the browser refuses access to
+    /// storage.
+    BackendUnavailable(Option<Source>),
+    /// The backend does not have what was asked for, such as a category of storage which it does
+    /// not keep.
+    Unsupported,
+    /// Any other problem, kept as it was reported so that its kind is not lost.
+    Other(std::io::Error),
+}
+
+impl StorageError {
+    /// Corrupt contents, with a description of the problem.
+    pub fn corrupt(source: impl Into<Source>) -> Self {
+        Self::Corrupt(Some(source.into()))
+    }
+
+    /// An unavailable backend, with a description of the problem.
+    pub fn backend(source: impl Into<Source>) -> Self {
+        Self::BackendUnavailable(Some(source.into()))
+    }
+
+    /// The kind of [std::io::Error] used for this error.
+    pub fn kind(&self) -> std::io::ErrorKind {
+        match self {
+            Self::NotFound => std::io::ErrorKind::NotFound,
+            Self::QuotaExceeded => std::io::ErrorKind::StorageFull,
+            Self::InvalidName(_) => std::io::ErrorKind::InvalidInput,
+            Self::PermissionDenied => std::io::ErrorKind::PermissionDenied,
+            Self::Corrupt(_) => std::io::ErrorKind::InvalidData,
+            Self::BackendUnavailable(_) => std::io::ErrorKind::Other,
+            Self::Unsupported => std::io::ErrorKind::Unsupported,
+            Self::Other(error) => error.kind(),
+        }
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "not found"),
+            Self::QuotaExceeded => write!(f, "storage quota exceeded"),
+            Self::InvalidName(error) => write!(f, "invalid name: {}", error),
+            Self::PermissionDenied => write!(f, "permission denied"),
+            Self::Corrupt(None) => write!(f, "corrupt contents"),
+            Self::Corrupt(Some(source)) => write!(f, "corrupt contents: {}", source),
+            Self::BackendUnavailable(None) => write!(f, "storage not available"),
+            Self::BackendUnavailable(Some(source)) => {
+                write!(f, "storage not available: {}", source)
+            }
+            Self::Unsupported => write!(f, "not supported by this storage"),
+            Self::Other(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for StorageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::InvalidName(error) => Some(error),
+            Self::Corrupt(Some(source)) | Self::BackendUnavailable(Some(source)) => {
+                Some(source.as_ref())
+            }
+            Self::Other(error) => error.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<InvalidNameError> for StorageError {
+    fn from(error: InvalidNameError) -> Self {
+        Self::InvalidName(error)
+    }
+}
+
+/// Locks are only poisoned when something panicked while using storage.
+impl<T> From<PoisonError<T>> for StorageError {
+    fn from(error: PoisonError<T>) -> Self {
+        Self::backend(error.to_string())
+    }
+}
+
+/// For mapping poisoned locks in backends straight to [std::io::Error].
+pub(crate) fn poisoned<T>(error: PoisonError<T>) -> std::io::Error {
+    StorageError::from(error).into()
+}
+
+impl From<StorageError> for std::io::Error {
+    fn from(error: StorageError) -> Self {
+        match error {
+            StorageError::Other(error) => error,
+            error => std::io::Error::new(error.kind(), error),
+        }
+    }
+}
+
+/// Recover the storage error from an error given by storage. Errors which did not start as storage
+/// errors, such as from the file system, are matched up by kind.
+impl From<std::io::Error> for StorageError {
+    fn from(error: std::io::Error) -> Self {
+        if error.get_ref().is_some_and(|inner| inner.is::<Self>()) {
+            let inner = error.into_inner().expect("should have inner error");
+            return *inner.downcast::<Self>().expect("should be storage error");
+        }
+        match error.kind() {
+            std::io::ErrorKind::NotFound => Self::NotFound,
+            std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded => {
+                Self::QuotaExceeded
+            }
+            std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem => {
+                Self::PermissionDenied
+            }
+            std::io::ErrorKind::InvalidData => Self::Corrupt(Some(error.into())),
+            std::io::ErrorKind::Unsupported => Self::Unsupported,
+            _ => match error
+                .get_ref()
+                .and_then(|inner| inner.downcast_ref::<InvalidNameError>())
+            {
+                Some(name_error) => Self::InvalidName(name_error.clone()),
+                None => Self::Other(error),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let error: std::io::Error = StorageError::corrupt("bad header").into();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(matches!(
+            StorageError::from(error),
+            StorageError::Corrupt(Some(source)) if source.to_string() == "bad header"
+        ));
+        let error: std::io::Error = InvalidNameError::Empty.into();
+        assert!(matches!(
+            StorageError::from(error),
+            StorageError::InvalidName(InvalidNameError::Empty)
+        ));
+    }
+
+    #[test]
+    fn from_kind() {
+        let error = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(matches!(StorageError::from(error), StorageError::NotFound));
+        let error = std::io::Error::from(std::io::ErrorKind::StorageFull);
+        assert!(matches!(
+            StorageError::from(error),
+            StorageError::QuotaExceeded
+        ));
+        let error = std::io::Error::from(std::io::ErrorKind::Unsupported);
+        assert!(matches!(
+            StorageError::from(error),
+            StorageError::Unsupported
+        ));
+        let error = std::io::Error::new(std::io::ErrorKind::WouldBlock, "file is locked");
+        let error = StorageError::from(error);
+        assert!(matches!(error, StorageError::Other(_)));
+        assert_eq!(error.to_string(), "file is locked");
+        assert_eq!(error.kind(), std::io::ErrorKind::WouldBlock);
+        let error = std::io::Error::from(error);
+        assert_eq!(error.kind(), std::io::ErrorKind::WouldBlock);
+        assert_eq!(error.to_string(), "file is locked");
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn outer_directory_error() {
+        use crate::storage::OuterDirectoryError;
+        assert!(matches!(
+            StorageError::from(OuterDirectoryError::NotAvailable),
+            StorageError::BackendUnavailable(None)
+        ));
+    }
+}
@@ -0,0 +1,578 @@
+//! Storage which fails on demand, wrapping any other storage, for testing how errors are handled.
+//!
+//! Failures are set up through [Faults], which is shared by everything made from the storage, so
+//! they can be changed part way through a test. Operations can be made to fail every time or after
+//! a number of successes. Writes can also be made to run out of space after a total number of
+//! bytes, or to stop part way through each file. A writer whose commit fails leaves the file as it
+//! was, as with a save which could not be finished.
+//!
+//! ```rust
+//! use std::io::{ErrorKind, Write};
+//! use dias::storage::faulty::{FaultyStorage, Operation};
+//! use dias::storage::{File, MemoryStorage, Storage, WritableDir, WritableFile};
+//!
+//! let mut storage = FaultyStorage::new(MemoryStorage::new());
+//! storage.faults().quota(Some(4));
+//! let mut file = storage.writable_data().unwrap().writable_file("save".into());
+//! let error = file.write_text().unwrap().write_all(b"level 1").unwrap_err();
+//! assert_eq!(error.kind(), ErrorKind::StorageFull);
+//!
+//! storage.faults().fail(Operation::Exists, ErrorKind::PermissionDenied);
+//! assert!(file.exists().is_err());
+//! ```
+
+use crate::storage::error::StorageError;
+use crate::storage::generic::{
+    Category, Commit, Dir, Entry, File, FileLock, Metadata, ParentDir, Space, Storage, Watcher,
+    WritableDir, WritableFile, WritableParentDir,
+};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Operations which can be made to fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Getting a category of storage.
+    Dir,
+    Exists,
+    Metadata,
+    /// Opening a file for reading.
+    Read,
+    /// Opening a file for writing, copying or moving to it.
+    Write,
+    Flush,
+    /// Committing a writer.
+    Commit,
+    /// Removing a file or subdirectory.
+    Remove,
+    Entries,
+    Clear,
+    Watch,
+    Lock,
+    Space,
+}
+
+struct Rule {
+    successes: usize,
+    kind: std::io::ErrorKind,
+}
+
+#[derive(Default)]
+struct State {
+    rules: HashMap<Operation, Rule>,
+    unavailable: HashSet<Category>,
+    quota: Option<u64>,
+    written: u64,
+    truncate: Option<u64>,
+}
+
+/// Failures to inject, shared by a [FaultyStorage] and everything made from it.
+#[derive(Clone, Default)]
+pub struct Faults {
+    state: Arc<Mutex<State>>,
+}
+
+impl Faults {
+    fn state(&self) -> MutexGuard<'_, State> {
+        // Panics in tests should not stop other faults from being checked.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Make an operation fail every time, with an error of the given kind.
+    pub fn fail(&self, operation: Operation, kind: std::io::ErrorKind) {
+        self.fail_after(operation, 0, kind);
+    }
+
+    /// Make an operation succeed the given number of times and then fail every time.
+    pub fn fail_after(&self, operation: Operation, successes: usize, kind: std::io::ErrorKind) {
+        self.state()
+            .rules
+            .insert(operation, Rule { successes, kind });
+    }
+
+    /// Stop an operation from failing.
+    pub fn succeed(&self, operation: Operation) {
+        self.state().rules.remove(&operation);
+    }
+
+    /// Make getting a category of storage fail with [StorageError::BackendUnavailable].
+    pub fn unavailable(&self, category: Category, unavailable: bool) {
+        let mut state = self.state();
+        if unavailable {
+            state.unavailable.insert(category);
+        } else {
+            state.unavailable.remove(&category);
+        }
+    }
+
+    /// Make writes fail with [std::io::ErrorKind::StorageFull] once the given number of bytes
+    /// have been written in total from now on.
+    pub fn quota(&self, bytes: Option<u64>) {
+        let mut state = self.state();
+        state.quota = bytes;
+        state.written = 0;
+    }
+
+    /// Make each writer stop accepting data after the given number of bytes, which makes
+    /// [Write::write_all] fail with [std::io::ErrorKind::WriteZero].
+    pub fn truncate_writes(&self, bytes: Option<u64>) {
+        self.state().truncate = bytes;
+    }
+
+    /// Stop all failures.
+    pub fn clear(&self) {
+        *self.state() = State::default();
+    }
+
+    fn check(&self, operation: Operation) -> std::io::Result<()> {
+        let mut state = self.state();
+        let Some(rule) = state.rules.get_mut(&operation) else {
+            return Ok(());
+        };
+        if rule.successes > 0 {
+            rule.successes -= 1;
+            return Ok(());
+        }
+        Err(std::io::Error::new(
+            rule.kind,
+            format!("injected {:?} failure", operation),
+        ))
+    }
+
+    fn check_category(&self, category: Category) -> Result<(), StorageError> {
+        if self.state().unavailable.contains(&category) {
+            return Err(StorageError::backend("injected unavailable category"));
+        }
+        Ok(())
+    }
+
+    /// Take as much of a write as is allowed, given what the writer has written so far.
+    fn allow_write(&self, written: u64, len: usize) -> std::io::Result<usize> {
+        let mut state = self.state();
+        let mut allowed = len as u64;
+        if let Some(truncate) = state.truncate {
+            allowed = allowed.min(truncate.saturating_sub(written));
+        }
+        if let Some(quota) = state.quota {
+            let left = quota.saturating_sub(state.written);
+            if allowed > left {
+                if left == 0 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::StorageFull,
+                        "injected quota exceeded",
+                    ));
+                }
+                allowed = left;
+            }
+        }
+        state.written += allowed;
+        Ok(allowed as usize)
+    }
+}
+
+pub struct FaultyWrite<W: Write> {
+    write: W,
+    faults: Faults,
+    written: u64,
+}
+
+impl<W: Write> Write for FaultyWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.faults.allow_write(self.written, buf.len())?;
+        self.write.write_all(&buf[..len])?;
+        self.written += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.faults.check(Operation::Flush)?;
+        self.write.flush()
+    }
+}
+
+impl<W: Commit> Commit for FaultyWrite<W> {
+    fn commit(&mut self) -> std::io::Result<()> {
+        self.faults.check(Operation::Commit)?;
+        self.write.commit()
+    }
+
+    fn abort(&mut self) {
+        self.write.abort()
+    }
+}
+
+pub struct FaultyFile<F> {
+    source: F,
+    faults: Faults,
+}
+
+impl<F> FaultyFile<F> {
+    fn write<W: Write>(&self, write: W) -> FaultyWrite<W> {
+        FaultyWrite {
+            write,
+            faults: self.faults.clone(),
+            written: 0,
+        }
+    }
+}
+
+impl<F: File> File for FaultyFile<F> {
+    type ReadText = F::ReadText;
+    type ReadBinary = F::ReadBinary;
+
+    fn exists(&self) -> std::io::Result<bool> {
+        self.faults.check(Operation::Exists)?;
+        self.source.exists()
+    }
+
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        self.faults.check(Operation::Metadata)?;
+        self.source.metadata()
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.faults.check(Operation::Watch)?;
+        self.source.watch()
+    }
+
+    fn read_text(&self) -> std::io::Result<Self::ReadText> {
+        self.faults.check(Operation::Read)?;
+        self.source.read_text()
+    }
+
+    fn read_binary(&self) -> std::io::Result<Self::ReadBinary> {
+        self.faults.check(Operation::Read)?;
+        self.source.read_binary()
+    }
+}
+
+impl<F: WritableFile> WritableFile for FaultyFile<F> {
+    type WriteText = FaultyWrite<F::WriteText>;
+    type WriteBinary = FaultyWrite<F::WriteBinary>;
+
+    fn remove(&mut self) -> std::io::Result<()> {
+        self.faults.check(Operation::Remove)?;
+        self.source.remove()
+    }
+
+    fn write_text(&mut self) -> std::io::Result<Self::WriteText> {
+        self.faults.check(Operation::Write)?;
+        let write = self.source.write_text()?;
+        Ok(self.write(write))
+    }
+
+    fn write_binary(&mut self) -> std::io::Result<Self::WriteBinary> {
+        self.faults.check(Operation::Write)?;
+        let write = self.source.write_binary()?;
+        Ok(self.write(write))
+    }
+
+    fn location(&self) -> std::io::Result<String> {
+        self.source.location()
+    }
+
+    fn copy_to(&self, target: &mut Self) -> std::io::Result<()> {
+        self.faults.check(Operation::Write)?;
+        self.source.copy_to(&mut target.source)
+    }
+
+    fn move_to(&mut self, target: &mut Self) -> std::io::Result<()> {
+        self.faults.check(Operation::Write)?;
+        self.source.move_to(&mut target.source)
+    }
+
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        self.faults.check(Operation::Lock)?;
+        self.source.try_lock()
+    }
+}
+
+pub struct FaultyDir<D> {
+    source: D,
+    faults: Faults,
+}
+
+impl<D> FaultyDir<D> {
+    fn wrap<T>(&self, source: T) -> FaultyDir<T> {
+        FaultyDir {
+            source,
+            faults: self.faults.clone(),
+        }
+    }
+
+    fn wrap_file<F>(&self, source: F) -> FaultyFile<F> {
+        FaultyFile {
+            source,
+            faults: self.faults.clone(),
+        }
+    }
+}
+
+impl<D: Dir> Dir for FaultyDir<D> {
+    type File = FaultyFile<D::File>;
+
+    fn file(&self, name: Cow<'static, str>) -> Self::File {
+        self.wrap_file(self.source.file(name))
+    }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        self.faults.check(Operation::Entries)?;
+        self.source.entries()
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.faults.check(Operation::Watch)?;
+        self.source.watch()
+    }
+}
+
+impl<D: WritableDir> WritableDir for FaultyDir<D> {
+    type WritableFile = FaultyFile<D::WritableFile>;
+
+    fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
+        let source = self.source.writable_file(name);
+        self.wrap_file(source)
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        self.faults.check(Operation::Clear)?;
+        self.source.clear()
+    }
+}
+
+impl<D: ParentDir> ParentDir for FaultyDir<D> {
+    type LeafDir = FaultyDir<D::LeafDir>;
+
+    fn subdir(&self, name: Cow<'static, str>) -> Self {
+        self.wrap(self.source.subdir(name))
+    }
+
+    fn into_leaf(self) -> Self::LeafDir {
+        FaultyDir {
+            source: self.source.into_leaf(),
+            faults: self.faults,
+        }
+    }
+}
+
+impl<D: WritableParentDir> WritableParentDir for FaultyDir<D> {
+    type WritableLeafDir = FaultyDir<D::WritableLeafDir>;
+
+    fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self {
+        let source = self.source.writable_subdir(name);
+        self.wrap(source)
+    }
+
+    fn into_writable_leaf(self) -> Self::WritableLeafDir {
+        FaultyDir {
+            source: self.source.into_writable_leaf(),
+            faults: self.faults,
+        }
+    }
+
+    fn remove_subdir(&mut self, name: Cow<'static, str>) -> std::io::Result<()> {
+        self.faults.check(Operation::Remove)?;
+        self.source.remove_subdir(name)
+    }
+}
+
+pub struct FaultyStorage<S> {
+    source: S,
+    faults: Faults,
+}
+
+impl<S: Storage> FaultyStorage<S> {
+    /// Wrap storage, with no failures set up yet.
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            faults: Faults::default(),
+        }
+    }
+
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S: Storage> Storage for FaultyStorage<S> {
+    type Dir = FaultyDir<S::Dir>;
+    type WritableDir = FaultyDir<S::WritableDir>;
+
+    fn dir(&self, category: Category) -> Result<Self::Dir, StorageError> {
+        self.faults.check(Operation::Dir)?;
+        self.faults.check_category(category)?;
+        Ok(FaultyDir {
+            source: self.source.dir(category)?,
+            faults: self.faults.clone(),
+        })
+    }
+
+    fn writable_dir(&mut self, category: Category) -> Result<Self::WritableDir, StorageError> {
+        self.faults.check(Operation::Dir)?;
+        self.faults.check_category(category)?;
+        Ok(FaultyDir {
+            source: self.source.writable_dir(category)?,
+            faults: self.faults.clone(),
+        })
+    }
+
+    async fn space(&self) -> std::io::Result<Space> {
+        self.faults.check(Operation::Space)?;
+        self.source.space().await
+    }
+
+    async fn request_persistence(&mut self) -> std::io::Result<bool> {
+        self.source.request_persistence().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::generic::tests as generic_tests;
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::io::{ErrorKind, Read};
+
+    fn make_storage() -> FaultyStorage<MemoryStorage> {
+        FaultyStorage::new(MemoryStorage::new())
+    }
+
+    fn read(file: &impl File) -> String {
+        let mut got = String::new();
+        file.read_text().unwrap().read_to_string(&mut got).unwrap();
+        got
+    }
+
+    #[test]
+    fn text_file() {
+        generic_tests::text_file(make_storage());
+    }
+
+    #[test]
+    fn binary_file() {
+        generic_tests::binary_file(make_storage());
+    }
+
+    #[test]
+    fn missing_file() {
+        generic_tests::missing_file(make_storage());
+    }
+
+    #[test]
+    fn commit_and_abort() {
+        generic_tests::commit_and_abort(make_storage());
+    }
+
+    #[test]
+    fn copy_and_move() {
+        generic_tests::copy_and_move(make_storage());
+    }
+
+    #[test]
+    fn listing() {
+        generic_tests::listing(make_storage());
+    }
+
+    #[test]
+    fn locking() {
+        generic_tests::locking(make_storage());
+    }
+
+    #[test]
+    fn operations() {
+        let mut storage = make_storage();
+        let faults = storage.faults().clone();
+        let mut dir = storage.writable_data().unwrap();
+        let mut file = dir.writable_file("a".into());
+        faults.fail_after(Operation::Exists, 1, ErrorKind::PermissionDenied);
+        assert!(!file.exists().unwrap());
+        assert_eq!(
+            file.exists().unwrap_err().kind(),
+            ErrorKind::PermissionDenied
+        );
+        assert!(file.exists().is_err());
+        faults.succeed(Operation::Exists);
+        assert!(!file.exists().unwrap());
+        faults.fail(Operation::Write, ErrorKind::ReadOnlyFilesystem);
+        assert_eq!(
+            file.write_text().err().unwrap().kind(),
+            ErrorKind::ReadOnlyFilesystem
+        );
+        faults.fail(Operation::Entries, ErrorKind::Other);
+        assert!(dir.entries().is_err());
+        faults.clear();
+        generic_tests::write_text(&mut file, b"a");
+        assert_eq!(dir.entries().unwrap(), vec![Entry::file("a")]);
+    }
+
+    #[test]
+    fn quota() {
+        let mut storage = make_storage();
+        storage.faults().quota(Some(8));
+        let mut dir = storage.writable_data().unwrap();
+        generic_tests::write_text(&mut dir.writable_file("a".into()), b"12345");
+        let mut file = dir.writable_file("b".into());
+        let mut write = file.write_text().unwrap();
+        assert_eq!(
+            write.write_all(b"12345").unwrap_err().kind(),
+            ErrorKind::StorageFull
+        );
+        drop(write);
+        assert!(!file.exists().unwrap());
+        storage.faults().quota(None);
+        generic_tests::write_text(&mut file, b"12345");
+        assert_eq!(read(&file), "12345");
+    }
+
+    #[test]
+    fn truncated_writes() {
+        let mut storage = make_storage();
+        storage.faults().truncate_writes(Some(3));
+        let mut dir = storage.writable_data().unwrap();
+        let mut file = dir.writable_file("a".into());
+        let mut write = file.write_binary().unwrap();
+        assert_eq!(
+            write.write_all(b"12345").unwrap_err().kind(),
+            ErrorKind::WriteZero
+        );
+        write.commit().unwrap();
+        assert_eq!(read(&file), "123");
+    }
+
+    #[test]
+    fn failed_commit() {
+        let mut storage = make_storage();
+        let mut dir = storage.writable_data().unwrap();
+        let mut file = dir.writable_file("a".into());
+        generic_tests::write_text(&mut file, b"saved");
+        let mut write = file.write_text().unwrap();
+        write.write_all(b"lost").unwrap();
+        storage.faults().fail(Operation::Flush, ErrorKind::Other);
+        assert!(write.flush().is_err());
+        storage.faults().fail(Operation::Commit, ErrorKind::Other);
+        assert!(write.commit().is_err());
+        drop(write);
+        assert_eq!(read(&file), "saved");
+    }
+
+    #[test]
+    fn unavailable() {
+        let mut storage = make_storage();
+        storage.faults().unavailable(Category::Config, true);
+        assert!(matches!(
+            storage.config(),
+            Err(StorageError::BackendUnavailable(_))
+        ));
+        assert!(storage.writable_config().is_err());
+        assert!(storage.data().is_ok());
+        storage.faults().unavailable(Category::Config, false);
+        assert!(storage.writable_config().is_ok());
+    }
+}
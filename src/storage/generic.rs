@@ -1,46 +1,276 @@
+use crate::storage::error::StorageError;
+use crate::storage::transaction::{recover, Transaction};
 use std::borrow::Cow;
 use std::error::Error;
 use std::fmt;
+use std::future::Future;
 use std::io::{Read, Write};
+use std::time::SystemTime;
 
+/// Error from getting an outer directory. Storage now gives [StorageError] instead, which this
+/// converts to.
+#[deprecated(note = "use StorageError")]
 #[derive(Debug)]
 pub enum OuterDirectoryError {
     NotAvailable,
 }
 
+#[allow(deprecated)]
 impl fmt::Display for OuterDirectoryError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
+#[allow(deprecated)]
 impl Error for OuterDirectoryError {}
 
+#[allow(deprecated)]
+impl From<OuterDirectoryError> for StorageError {
+    fn from(error: OuterDirectoryError) -> Self {
+        match error {
+            OuterDirectoryError::NotAvailable => Self::BackendUnavailable(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    File,
+    Dir,
+}
+
+/// An item found when listing a directory.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Entry {
+    pub name: String,
+    pub kind: EntryKind,
+}
+
+impl Entry {
+    pub fn file(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            kind: EntryKind::File,
+        }
+    }
+
+    pub fn dir(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            kind: EntryKind::Dir,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// Size of the stored contents in bytes.
+    pub len: u64,
+    /// When the file was last written, if known.
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// A change to a watched file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Change {
+    /// Path of the file relative to the watched directory, with subdirectories separated by `/`.
+    /// For a watched file this is just its name.
+    pub name: String,
+    pub kind: ChangeKind,
+}
+
+impl Change {
+    pub fn new(name: impl Into<String>, kind: ChangeKind) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+        }
+    }
+}
+
+/// Collects changes to a watched file or directory until they are taken with
+/// [Watcher::changes]. Several changes to the same file in between are combined into one.
+pub struct Watcher {
+    poll: Box<dyn FnMut() -> std::io::Result<Vec<Change>>>,
+}
+
+impl Watcher {
+    pub fn new(poll: impl FnMut() -> std::io::Result<Vec<Change>> + 'static) -> Self {
+        Self {
+            poll: Box::new(poll),
+        }
+    }
+
+    /// Take the changes since the last call, or since the watcher was made. Cheap enough to call
+    /// every frame, except on standard where it has to look at every watched file.
+    pub fn changes(&mut self) -> std::io::Result<Vec<Change>> {
+        (self.poll)()
+    }
+}
+
+/// An advisory lock on a file, released when dropped. Locks only keep out other users who also
+/// lock the file.
+pub struct FileLock {
+    renew: Box<dyn FnMut() -> std::io::Result<()>>,
+    release: Option<Box<dyn FnOnce()>>,
+}
+
+impl FileLock {
+    pub fn new(
+        renew: impl FnMut() -> std::io::Result<()> + 'static,
+        release: impl FnOnce() + 'static,
+    ) -> Self {
+        Self {
+            renew: Box::new(renew),
+            release: Some(Box::new(release)),
+        }
+    }
+
+    /// Keep holding the lock. Only needed on web, where locks lapse if they are not renewed.
+    pub fn renew(&mut self) -> std::io::Result<()> {
+        (self.renew)()
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        if let Some(release) = self.release.take() {
+            release();
+        }
+    }
+}
+
 pub trait File {
     type ReadText: Read;
     type ReadBinary: Read;
     fn exists(&self) -> std::io::Result<bool>;
+    fn metadata(&self) -> std::io::Result<Metadata>;
+    /// Watch for this file being created, modified or removed.
+    fn watch(&self) -> std::io::Result<Watcher>;
+    /// Read the contents. Text and binary readers give the same bytes, however the file was
+    /// written.
     fn read_text(&self) -> std::io::Result<Self::ReadText>;
     fn read_binary(&self) -> std::io::Result<Self::ReadBinary>;
 }
 
+/// A writer for replacing the contents of a file. Nothing written is visible until [Commit::commit]
+/// succeeds, which replaces the contents all at once. Dropping the writer without committing, such
+/// as after an error partway through writing, leaves the file as it was. Flushing does not make the
+/// contents visible.
+///
+/// Once committed or aborted, the writer can not be written to or committed again.
+pub trait Commit: Write {
+    /// Replace the contents of the file with everything written.
+    fn commit(&mut self) -> std::io::Result<()>;
+    /// Discard everything written, leaving the file as it was.
+    fn abort(&mut self);
+}
+
+impl<C: Commit + ?Sized> Commit for Box<C> {
+    fn commit(&mut self) -> std::io::Result<()> {
+        (**self).commit()
+    }
+
+    fn abort(&mut self) {
+        (**self).abort()
+    }
+}
+
+/// Error from using a writer after it was committed or aborted.
+pub(crate) fn finished_writer() -> std::io::Error {
+    std::io::Error::other("writer was already committed or aborted")
+}
+
 pub trait WritableFile: File {
-    type WriteText: Write;
-    type WriteBinary: Write;
+    type WriteText: Commit;
+    type WriteBinary: Commit;
 
+    /// Remove the file. Succeeds without doing anything if there is no file.
     fn remove(&mut self) -> std::io::Result<()>;
     fn write_text(&mut self) -> std::io::Result<Self::WriteText>;
     fn write_binary(&mut self) -> std::io::Result<Self::WriteBinary>;
+    /// Where the file is kept, which is the same for every handle on the same file and different
+    /// for any other file. Used to tell when a file is copied or moved onto itself.
+    fn location(&self) -> std::io::Result<String>;
+
+    /// Copy the contents of this file to `target`, replacing anything already there.
+    fn copy_to(&self, target: &mut Self) -> std::io::Result<()>
+    where
+        Self: Sized,
+    {
+        if self.location()? == target.location()? {
+            return Ok(());
+        }
+        let mut read = self.read_binary()?;
+        let mut write = target.write_binary()?;
+        std::io::copy(&mut read, &mut write)?;
+        write.commit()
+    }
+
+    /// Move this file to `target`, replacing anything already there. Backends do this without
+    /// copying the contents where they can.
+    fn move_to(&mut self, target: &mut Self) -> std::io::Result<()>
+    where
+        Self: Sized,
+    {
+        if self.location()? == target.location()? {
+            return Ok(());
+        }
+        self.copy_to(target)?;
+        self.remove()
+    }
+
+    /// Lock the file against anyone else locking it, including other copies of the application,
+    /// until the lock is dropped. Fails with [std::io::ErrorKind::WouldBlock] if the file is
+    /// already locked, so callers can give up or try again later. Fails with
+    /// [std::io::ErrorKind::Unsupported] on backends without locking.
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "locking is not supported",
+        ))
+    }
 }
 
 pub trait Dir {
     type File: File;
     fn file(&self, name: Cow<'static, str>) -> Self::File;
+    /// List the files and subdirectories directly inside this directory, sorted by name. A
+    /// directory that has never been written to is empty.
+    fn entries(&self) -> std::io::Result<Vec<Entry>>;
+    /// Watch for files being created, modified or removed anywhere under this directory.
+    fn watch(&self) -> std::io::Result<Watcher>;
 }
 
 pub trait WritableDir: Dir {
     type WritableFile: WritableFile;
     fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile;
+    /// Remove everything inside this directory, including subdirectories.
+    fn clear(&mut self) -> std::io::Result<()>;
+
+    /// Rename a file in this directory, replacing any file already called `to`.
+    fn rename(&mut self, from: Cow<'static, str>, to: Cow<'static, str>) -> std::io::Result<()> {
+        let mut target = self.writable_file(to);
+        self.writable_file(from).move_to(&mut target)
+    }
+
+    /// Start making changes to files in this directory which all happen at once, after finishing
+    /// any earlier transaction which was interrupted. See [crate::storage::transaction].
+    fn transaction(&mut self) -> std::io::Result<Transaction<'_, Self>>
+    where
+        Self: Sized,
+    {
+        recover(self)?;
+        Ok(Transaction::begin(self))
+    }
 }
 
 pub trait ParentDir: Dir {
@@ -53,23 +283,125 @@ pub trait WritableParentDir: WritableDir + ParentDir {
     type WritableLeafDir: WritableDir;
     fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self;
     fn into_writable_leaf(self) -> Self::WritableLeafDir;
+    /// Remove a subdirectory and everything inside it. Does nothing if it does not exist.
+    fn remove_subdir(&mut self, name: Cow<'static, str>) -> std::io::Result<()>;
+}
+
+/// Space used and available for storage, in bytes. Either may be unknown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Space {
+    /// Space taken up by the storage itself.
+    pub used: Option<u64>,
+    /// Space left before writes start failing.
+    pub available: Option<u64>,
+}
+
+/// Kinds of storage directory, which can be in different places depending on the platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Category {
+    /// Files the application keeps, such as progress and downloaded content.
+    Data,
+    /// Settings.
+    Config,
+    /// Files which can be rebuilt if lost.
+    Cache,
+    /// Files which should survive restarts but are not worth backing up, such as history and
+    /// window layout.
+    State,
+    /// Files which only last until logout or the end of the browser session, such as lock files.
+    Runtime,
+    /// Log files.
+    Logs,
+    /// Saves which the user should be able to find, such as exported games.
+    Saves,
+    /// Screenshots which the user should be able to find.
+    Screenshots,
+}
+
+impl Category {
+    pub const ALL: [Category; 8] = [
+        Category::Data,
+        Category::Config,
+        Category::Cache,
+        Category::State,
+        Category::Runtime,
+        Category::Logs,
+        Category::Saves,
+        Category::Screenshots,
+    ];
+
+    /// Name of the category, used for its directory where backends do not have their own.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Data => "data",
+            Category::Config => "config",
+            Category::Cache => "cache",
+            Category::State => "state",
+            Category::Runtime => "runtime",
+            Category::Logs => "logs",
+            Category::Saves => "saves",
+            Category::Screenshots => "screenshots",
+        }
+    }
 }
 
 pub trait Storage {
     type Dir: ParentDir;
     type WritableDir: WritableParentDir;
-    fn data(&self) -> Result<Self::Dir, OuterDirectoryError>;
-    fn config(&self) -> Result<Self::Dir, OuterDirectoryError>;
-    fn cache(&self) -> Result<Self::Dir, OuterDirectoryError>;
-    fn writable_data(&mut self) -> Result<Self::WritableDir, OuterDirectoryError>;
-    fn writable_config(&mut self) -> Result<Self::WritableDir, OuterDirectoryError>;
-    fn writable_cache(&mut self) -> Result<Self::WritableDir, OuterDirectoryError>;
+    fn dir(&self, category: Category) -> Result<Self::Dir, StorageError>;
+    fn writable_dir(&mut self, category: Category) -> Result<Self::WritableDir, StorageError>;
+
+    fn data(&self) -> Result<Self::Dir, StorageError> {
+        self.dir(Category::Data)
+    }
+
+    fn config(&self) -> Result<Self::Dir, StorageError> {
+        self.dir(Category::Config)
+    }
+
+    fn cache(&self) -> Result<Self::Dir, StorageError> {
+        self.dir(Category::Cache)
+    }
+
+    fn writable_data(&mut self) -> Result<Self::WritableDir, StorageError> {
+        self.writable_dir(Category::Data)
+    }
+
+    fn writable_config(&mut self) -> Result<Self::WritableDir, StorageError> {
+        self.writable_dir(Category::Config)
+    }
+
+    fn writable_cache(&mut self) -> Result<Self::WritableDir, StorageError> {
+        self.writable_dir(Category::Cache)
+    }
+
+    /// Find out how much space is used and available. Only web needs to wait for the result.
+    fn space(&self) -> impl Future<Output = std::io::Result<Space>>;
+    /// Ask for stored data to be kept even when the system is low on space, which browsers do
+    /// not promise by default. Gives whether the data will be kept.
+    fn request_persistence(&mut self) -> impl Future<Output = std::io::Result<bool>>;
 }
 
 #[cfg(test)]
 pub(super) mod tests {
     use super::*;
+    use std::fmt;
     use std::io::{Read, Write};
+    use std::time::Duration;
+
+    /// Write and commit the whole contents of a file as text.
+    pub fn write_text(file: &mut impl WritableFile, contents: &[u8]) {
+        let mut write = file.write_text().unwrap();
+        write.write_all(contents).unwrap();
+        write.commit().unwrap();
+    }
+
+    /// Write and commit the whole contents of a file as binary.
+    pub fn write_binary(file: &mut impl WritableFile, contents: &[u8]) {
+        let mut write = file.write_binary().unwrap();
+        write.write_all(contents).unwrap();
+        write.commit().unwrap();
+    }
 
     pub fn text_file(mut storage: impl Storage) {
         let text = "hello world";
@@ -78,10 +410,7 @@ pub(super) mod tests {
             .writable_data()
             .unwrap()
             .writable_file("test".into());
-        file.write_text()
-            .unwrap()
-            .write_all(text.as_bytes())
-            .unwrap();
+        write_text(&mut file, text.as_bytes());
         assert!(file.exists().unwrap());
         let mut got = String::new();
         file.read_text().unwrap().read_to_string(&mut got).unwrap();
@@ -92,6 +421,9 @@ pub(super) mod tests {
         let mut got = String::new();
         file.read_text().unwrap().read_to_string(&mut got).unwrap();
         assert_eq!(got, text);
+        let mut got = Vec::new();
+        file.read_binary().unwrap().read_to_end(&mut got).unwrap();
+        assert_eq!(got, text.as_bytes());
 
         let mut file = storage
             .writable_data()
@@ -111,10 +443,7 @@ pub(super) mod tests {
             .writable_data()
             .unwrap()
             .writable_file("test".into());
-        file.write_text()
-            .unwrap()
-            .write_all(text.as_bytes())
-            .unwrap();
+        write_text(&mut file, text.as_bytes());
         assert!(file.exists().unwrap());
         let mut got = String::new();
         file.read_text().unwrap().read_to_string(&mut got).unwrap();
@@ -137,6 +466,65 @@ pub(super) mod tests {
         assert!(!file.exists().unwrap());
     }
 
+    pub fn categories(mut storage: impl Storage) {
+        for category in Category::ALL {
+            write_text(
+                &mut storage
+                    .writable_dir(category)
+                    .unwrap()
+                    .writable_file("category".into()),
+                category.name().as_bytes(),
+            );
+        }
+        for category in Category::ALL {
+            let mut got = String::new();
+            storage
+                .dir(category)
+                .unwrap()
+                .file("category".into())
+                .read_text()
+                .unwrap()
+                .read_to_string(&mut got)
+                .unwrap();
+            assert_eq!(got, category.name());
+        }
+        let mut got = String::new();
+        storage
+            .config()
+            .unwrap()
+            .file("category".into())
+            .read_text()
+            .unwrap()
+            .read_to_string(&mut got)
+            .unwrap();
+        assert_eq!(got, "config");
+    }
+
+    pub fn locking(mut storage: impl Storage) {
+        let mut dir = storage
+            .writable_data()
+            .unwrap()
+            .writable_subdir("locking".into());
+        dir.clear().unwrap();
+        let mut file = dir.writable_file("save".into());
+        let lock = file.try_lock().unwrap();
+        assert_eq!(
+            dir.writable_file("save".into())
+                .try_lock()
+                .err()
+                .unwrap()
+                .kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+        let other = dir.writable_file("other".into()).try_lock().unwrap();
+        write_text(&mut file, b"locked");
+        assert_eq!(dir.entries().unwrap(), vec![Entry::file("save")]);
+        drop(lock);
+        let mut lock = dir.writable_file("save".into()).try_lock().unwrap();
+        lock.renew().unwrap();
+        drop(other);
+    }
+
     pub fn file_uniqueness(mut storage: impl Storage) {
         let to_check = vec![
             (storage.writable_data().unwrap(), "data", "one", "a"),
@@ -158,13 +546,12 @@ pub(super) mod tests {
                 "contents {} {} {}",
                 outer_dir_name, inner_dir_name, file_name
             );
-            outer_dir
-                .subdir((*inner_dir_name).into())
-                .writable_file((*file_name).into())
-                .write_text()
-                .unwrap()
-                .write_all(value.as_bytes())
-                .unwrap();
+            write_text(
+                &mut outer_dir
+                    .subdir((*inner_dir_name).into())
+                    .writable_file((*file_name).into()),
+                value.as_bytes(),
+            );
         }
 
         for (outer_dir, outer_dir_name, inner_dir_name, file_name) in to_check.iter() {
@@ -183,4 +570,405 @@ pub(super) mod tests {
             assert_eq!(got, value);
         }
     }
+
+    pub fn metadata(mut storage: impl Storage) {
+        let before = crate::storage::time::now() - Duration::from_secs(2);
+
+        let mut file = storage
+            .writable_data()
+            .unwrap()
+            .writable_file("metadata".into());
+        write_binary(&mut file, &[0, 1, 2, 255]);
+        let metadata = file.metadata().unwrap();
+        assert_eq!(metadata.len, 4);
+        assert!(metadata.modified.unwrap() >= before);
+
+        write_text(&mut file, "hello world".as_bytes());
+        let metadata = storage
+            .data()
+            .unwrap()
+            .file("metadata".into())
+            .metadata()
+            .unwrap();
+        assert_eq!(metadata.len, 11);
+        assert!(metadata.modified.unwrap() >= before);
+
+        file.remove().unwrap();
+        assert_eq!(
+            file.metadata().unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    pub fn missing_file(mut storage: impl Storage) {
+        fn check<T: fmt::Debug>(result: std::io::Result<T>) {
+            let error = result.unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+            assert!(matches!(StorageError::from(error), StorageError::NotFound));
+        }
+
+        let mut dir = storage
+            .writable_data()
+            .unwrap()
+            .writable_subdir("missing".into());
+        let file = dir.file("file".into());
+        assert!(!file.exists().unwrap());
+        check(file.read_text().map(|_| ()));
+        check(file.read_binary().map(|_| ()));
+        check(file.metadata());
+        dir.writable_file("file".into()).remove().unwrap();
+        assert!(!file.exists().unwrap());
+        let file = storage
+            .data()
+            .unwrap()
+            .subdir("missing".into())
+            .file("file".into());
+        check(file.read_text().map(|_| ()));
+    }
+
+    pub fn commit_and_abort(mut storage: impl Storage) {
+        fn read(file: &impl File) -> String {
+            let mut got = String::new();
+            file.read_text().unwrap().read_to_string(&mut got).unwrap();
+            got
+        }
+        let mut dir = storage
+            .writable_data()
+            .unwrap()
+            .writable_subdir("commit_and_abort".into());
+        dir.clear().unwrap();
+        let mut file = dir.writable_file("save".into());
+
+        let mut write = file.write_text().unwrap();
+        write.write_all(b"partial").unwrap();
+        write.flush().unwrap();
+        assert!(!file.exists().unwrap());
+        drop(write);
+        assert!(!file.exists().unwrap());
+        assert_eq!(dir.entries().unwrap(), vec![]);
+
+        write_text(&mut file, b"saved");
+        let mut write = file.write_text().unwrap();
+        write.write_all(b"half").unwrap();
+        write.abort();
+        assert!(write.write_all(b"more").is_err());
+        assert!(write.commit().is_err());
+        drop(write);
+        assert_eq!(read(&file), "saved");
+
+        let mut write = file.write_text().unwrap();
+        write.write_all(b"new").unwrap();
+        write.commit().unwrap();
+        assert!(write.write_all(b"more").is_err());
+        assert!(write.commit().is_err());
+        drop(write);
+        assert_eq!(read(&file), "new");
+        assert_eq!(dir.entries().unwrap(), vec![Entry::file("save")]);
+    }
+
+    pub fn copy_and_move(mut storage: impl Storage) {
+        fn read(file: &impl File) -> String {
+            let mut got = String::new();
+            file.read_text().unwrap().read_to_string(&mut got).unwrap();
+            got
+        }
+        let mut dir = storage
+            .writable_data()
+            .unwrap()
+            .writable_subdir("copy_and_move".into());
+
+        let mut a = dir.writable_file("a".into());
+        write_text(&mut a, b"hello");
+        let mut b = dir.writable_file("b".into());
+        write_text(&mut b, b"replaced");
+        a.copy_to(&mut b).unwrap();
+        assert_eq!(read(&a), "hello");
+        assert_eq!(read(&b), "hello");
+
+        let mut c = dir
+            .writable_subdir("other".into())
+            .writable_file("c".into());
+        a.move_to(&mut c).unwrap();
+        assert!(!a.exists().unwrap());
+        assert_eq!(read(&c), "hello");
+        assert_eq!(
+            a.move_to(&mut c).unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+
+        dir.rename("b".into(), "d".into()).unwrap();
+        assert!(!dir.file("b".into()).exists().unwrap());
+        assert_eq!(read(&dir.file("d".into())), "hello");
+
+        let mut d = dir.writable_file("d".into());
+        d.move_to(&mut dir.writable_file("d".into())).unwrap();
+        assert_eq!(read(&d), "hello");
+        d.copy_to(&mut dir.writable_file("d".into())).unwrap();
+        assert_eq!(read(&d), "hello");
+        dir.rename("d".into(), "d".into()).unwrap();
+        assert_eq!(read(&d), "hello");
+    }
+
+    pub fn removal(mut storage: impl Storage) {
+        let mut dir = storage
+            .writable_data()
+            .unwrap()
+            .writable_subdir("removal".into());
+        for (mut dir, name) in [
+            (dir.writable_subdir("sub".into()), "a"),
+            (
+                dir.writable_subdir("sub".into())
+                    .writable_subdir("deeper".into()),
+                "b",
+            ),
+            (dir.writable_subdir("other".into()), "c"),
+            (dir.writable_subdir("subway".into()), "d"),
+        ] {
+            write_text(&mut dir.writable_file(name.into()), name.as_bytes());
+        }
+        write_text(&mut dir.writable_file("e".into()), b"e");
+
+        dir.remove_subdir("sub".into()).unwrap();
+        assert_eq!(
+            dir.entries().unwrap(),
+            vec![Entry::file("e"), Entry::dir("other"), Entry::dir("subway")]
+        );
+        assert!(!dir
+            .subdir("sub".into())
+            .subdir("deeper".into())
+            .file("b".into())
+            .exists()
+            .unwrap());
+        assert!(dir
+            .subdir("subway".into())
+            .file("d".into())
+            .exists()
+            .unwrap());
+        dir.remove_subdir("missing".into()).unwrap();
+
+        dir.clear().unwrap();
+        assert_eq!(dir.entries().unwrap(), vec![]);
+        assert!(!dir.file("e".into()).exists().unwrap());
+
+        let mut kept = storage
+            .writable_data()
+            .unwrap()
+            .writable_file("kept".into());
+        write_text(&mut kept, b"kept");
+        write_text(
+            &mut storage
+                .writable_cache()
+                .unwrap()
+                .writable_file("cleared".into()),
+            b"cleared",
+        );
+        storage.writable_cache().unwrap().clear().unwrap();
+        assert_eq!(storage.cache().unwrap().entries().unwrap(), vec![]);
+        assert!(kept.exists().unwrap());
+        kept.remove().unwrap();
+    }
+
+    pub fn names(mut storage: impl Storage) {
+        fn assert_invalid<T>(result: std::io::Result<T>) {
+            match result {
+                Ok(_) => panic!("invalid name should be rejected"),
+                Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput),
+            }
+        }
+
+        let mut dir = storage
+            .writable_data()
+            .unwrap()
+            .writable_subdir("names".into());
+        write_text(
+            &mut dir.writable_subdir("a".into()).writable_file("b".into()),
+            b"inner",
+        );
+
+        for name in [
+            "",
+            ".",
+            "..",
+            "a/b",
+            "../../.bashrc",
+            "/abs",
+            "a\\b",
+            "C:",
+            "CON",
+            "nul.txt",
+            "trailing.",
+        ] {
+            let mut file = dir.writable_file(name.into());
+            assert_invalid(file.exists());
+            assert_invalid(file.metadata());
+            assert_invalid(file.read_text());
+            assert_invalid(file.write_text());
+            assert_invalid(file.write_binary());
+            assert_invalid(file.remove());
+            assert_invalid(dir.rename("a".into(), name.into()));
+            let mut subdir = dir.writable_subdir(name.into());
+            assert_invalid(subdir.entries());
+            assert_invalid(subdir.clear());
+            assert_invalid(subdir.file("ok".into()).exists());
+            assert_invalid(subdir.subdir("ok".into()).entries());
+            assert_invalid(dir.remove_subdir(name.into()));
+        }
+
+        let mut got = String::new();
+        dir.subdir("a".into())
+            .file("b".into())
+            .read_text()
+            .unwrap()
+            .read_to_string(&mut got)
+            .unwrap();
+        assert_eq!(got, "inner");
+
+        for name in [".hidden", "save 1.json", "ünïcode", "CONSOLE"] {
+            let mut file = dir.writable_file(name.into());
+            write_text(&mut file, b"ok");
+            assert!(file.exists().unwrap());
+        }
+        assert_eq!(
+            dir.entries().unwrap(),
+            vec![
+                Entry::file(".hidden"),
+                Entry::file("CONSOLE"),
+                Entry::dir("a"),
+                Entry::file("save 1.json"),
+                Entry::file("ünïcode"),
+            ]
+        );
+    }
+
+    pub async fn space(mut storage: impl Storage) {
+        let before = storage.space().await.unwrap();
+        let mut file = storage
+            .writable_data()
+            .unwrap()
+            .writable_file("space".into());
+        write_binary(&mut file, &[1; 1000]);
+        let after = storage.space().await.unwrap();
+        assert!(after.used.unwrap() >= before.used.unwrap() + 1000);
+        file.remove().unwrap();
+    }
+
+    fn sorted(mut changes: Vec<Change>) -> Vec<Change> {
+        changes.sort_by(|a, b| a.name.cmp(&b.name));
+        changes
+    }
+
+    pub fn watching(mut storage: impl Storage) {
+        let mut dir = storage
+            .writable_data()
+            .unwrap()
+            .writable_subdir("watching".into());
+        dir.clear().unwrap();
+        let mut dir_watcher = dir.watch().unwrap();
+        let mut file = dir.writable_file("a".into());
+        let mut file_watcher = file.watch().unwrap();
+        assert_eq!(dir_watcher.changes().unwrap(), vec![]);
+        assert_eq!(file_watcher.changes().unwrap(), vec![]);
+
+        write_text(&mut file, b"1");
+        write_text(
+            &mut dir.writable_subdir("sub".into()).writable_file("b".into()),
+            b"2",
+        );
+        assert_eq!(
+            sorted(dir_watcher.changes().unwrap()),
+            vec![
+                Change::new("a", ChangeKind::Created),
+                Change::new("sub/b", ChangeKind::Created),
+            ]
+        );
+        assert_eq!(
+            file_watcher.changes().unwrap(),
+            vec![Change::new("a", ChangeKind::Created)]
+        );
+
+        write_text(&mut file, b"11");
+        assert_eq!(
+            dir_watcher.changes().unwrap(),
+            vec![Change::new("a", ChangeKind::Modified)]
+        );
+        dir.rename("a".into(), "a".into()).unwrap();
+        assert_eq!(dir_watcher.changes().unwrap(), vec![]);
+        file.remove().unwrap();
+        write_text(&mut dir.writable_file("c".into()), b"3");
+        dir.writable_file("c".into()).remove().unwrap();
+        assert_eq!(
+            dir_watcher.changes().unwrap(),
+            vec![Change::new("a", ChangeKind::Removed)]
+        );
+        assert_eq!(
+            file_watcher.changes().unwrap(),
+            vec![Change::new("a", ChangeKind::Removed)]
+        );
+        assert_eq!(dir_watcher.changes().unwrap(), vec![]);
+
+        let mut transaction = dir.transaction().unwrap();
+        let mut write = transaction.write_text("d".into()).unwrap();
+        write.write_all(b"4").unwrap();
+        write.commit().unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(
+            dir_watcher.changes().unwrap(),
+            vec![Change::new("d", ChangeKind::Created)]
+        );
+    }
+
+    pub fn listing(mut storage: impl Storage) {
+        let mut base = storage
+            .writable_data()
+            .unwrap()
+            .writable_subdir("listing".into());
+        for (dir, name) in [
+            (base.writable_subdir("top".into()), "b"),
+            (base.writable_subdir("top".into()), "a"),
+            (
+                base.writable_subdir("top".into())
+                    .writable_subdir("sub".into()),
+                "c",
+            ),
+            (
+                base.writable_subdir("top".into())
+                    .writable_subdir("sub".into())
+                    .writable_subdir("deeper".into()),
+                "d",
+            ),
+            (base.writable_subdir("topper".into()), "e"),
+        ] {
+            let mut dir = dir;
+            write_text(&mut dir.writable_file(name.into()), name.as_bytes());
+        }
+
+        let base = storage.data().unwrap().subdir("listing".into());
+        assert_eq!(
+            base.entries().unwrap(),
+            vec![Entry::dir("top"), Entry::dir("topper")]
+        );
+        let top = base.subdir("top".into());
+        assert_eq!(
+            top.entries().unwrap(),
+            vec![Entry::file("a"), Entry::file("b"), Entry::dir("sub")]
+        );
+        assert_eq!(
+            top.subdir("sub".into()).entries().unwrap(),
+            vec![Entry::file("c"), Entry::dir("deeper")]
+        );
+        assert_eq!(base.subdir("missing".into()).entries().unwrap(), vec![]);
+
+        storage
+            .writable_data()
+            .unwrap()
+            .writable_subdir("listing".into())
+            .writable_subdir("top".into())
+            .writable_file("a".into())
+            .remove()
+            .unwrap();
+        assert_eq!(
+            top.entries().unwrap(),
+            vec![Entry::file("b"), Entry::dir("sub")]
+        );
+    }
 }
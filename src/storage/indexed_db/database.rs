@@ -0,0 +1,225 @@
+use crate::storage::error::StorageError;
+use crate::storage::time::now;
+use js_sys::{Object, Promise, Reflect, Uint8Array};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbObjectStore, IdbRequest, IdbTransactionMode};
+
+static STORE_NAME: &str = "files";
+static CONTENTS_FIELD: &str = "contents";
+static MODIFIED_FIELD: &str = "modified";
+
+#[derive(Debug)]
+pub enum IndexedDbAvailabilityError {
+    NoWindow,
+    NoIndexedDb,
+    CannotOpen,
+    CannotLoad,
+}
+
+impl fmt::Display for IndexedDbAvailabilityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for IndexedDbAvailabilityError {}
+
+pub struct Stored {
+    pub contents: Vec<u8>,
+    pub modified: SystemTime,
+}
+
+impl Stored {
+    pub fn new(contents: Vec<u8>) -> Self {
+        Self {
+            contents,
+            modified: now(),
+        }
+    }
+
+    fn to_js(&self) -> Result<JsValue, JsValue> {
+        let modified = self
+            .modified
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as f64;
+        let value = Object::new();
+        Reflect::set(
+            &value,
+            &CONTENTS_FIELD.into(),
+            &Uint8Array::from(self.contents.as_slice()),
+        )?;
+        Reflect::set(&value, &MODIFIED_FIELD.into(), &modified.into())?;
+        Ok(value.into())
+    }
+
+    fn from_js(value: &JsValue) -> Option<Self> {
+        let contents = Reflect::get(value, &CONTENTS_FIELD.into())
+            .ok()?
+            .dyn_into::<Uint8Array>()
+            .ok()?
+            .to_vec();
+        let modified = Reflect::get(value, &MODIFIED_FIELD.into()).ok()?.as_f64()?;
+        Some(Self {
+            contents,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_millis(modified as u64),
+        })
+    }
+}
+
+/// A promise settled by the outcome of a request.
+fn request_done(request: &IdbRequest) -> Promise {
+    Promise::new(&mut |resolve, reject| {
+        request.set_onsuccess(Some(&resolve));
+        request.set_onerror(Some(&reject));
+    })
+}
+
+/// Changes which have not completed yet. Requests are forgotten as they complete, keeping only
+/// the first failure until it is reported.
+#[derive(Default)]
+struct Pending {
+    count: usize,
+    error: Option<String>,
+    /// Resolve functions of flushes waiting for every change to complete.
+    waiting: Vec<js_sys::Function>,
+}
+
+impl Pending {
+    fn done(&mut self, error: Option<String>) {
+        self.count -= 1;
+        if self.error.is_none() {
+            self.error = error;
+        }
+        if self.count == 0 {
+            for resolve in std::mem::take(&mut self.waiting) {
+                let _ = resolve.call0(&JsValue::UNDEFINED);
+            }
+        }
+    }
+}
+
+/// Handle to the database. Changes are sent as they happen, but complete in the background.
+pub struct Database {
+    db: IdbDatabase,
+    pending: Rc<RefCell<Pending>>,
+}
+
+impl Database {
+    /// Open the database and load everything in it.
+    pub async fn open(
+        name: &str,
+    ) -> Result<(Self, HashMap<String, Stored>), IndexedDbAvailabilityError> {
+        let factory = web_sys::window()
+            .ok_or(IndexedDbAvailabilityError::NoWindow)?
+            .indexed_db()
+            .map_err(|_| IndexedDbAvailabilityError::NoIndexedDb)?
+            .ok_or(IndexedDbAvailabilityError::NoIndexedDb)?;
+        let request = factory
+            .open_with_u32(name, 1)
+            .map_err(|_| IndexedDbAvailabilityError::CannotOpen)?;
+        let upgrade_request = request.clone();
+        let on_upgrade = Closure::<dyn FnMut()>::new(move || {
+            if let Ok(db) = upgrade_request.result() {
+                let db: IdbDatabase = db.unchecked_into();
+                if !db.object_store_names().contains(STORE_NAME) {
+                    let _ = db.create_object_store(STORE_NAME);
+                }
+            }
+        });
+        request.set_onupgradeneeded(Some(on_upgrade.as_ref().unchecked_ref()));
+        let opened = JsFuture::from(request_done(&request)).await;
+        request.set_onupgradeneeded(None);
+        opened.map_err(|_| IndexedDbAvailabilityError::CannotOpen)?;
+        let db: IdbDatabase = request
+            .result()
+            .map_err(|_| IndexedDbAvailabilityError::CannotOpen)?
+            .unchecked_into();
+
+        let database = Self {
+            db,
+            pending: Rc::default(),
+        };
+        let files = database
+            .load()
+            .await
+            .map_err(|_| IndexedDbAvailabilityError::CannotLoad)?;
+        Ok((database, files))
+    }
+
+    async fn load(&self) -> Result<HashMap<String, Stored>, JsValue> {
+        let store = self.store(IdbTransactionMode::Readonly)?;
+        let keys_request = store.get_all_keys()?;
+        let values_request = store.get_all()?;
+        JsFuture::from(request_done(&keys_request)).await?;
+        JsFuture::from(request_done(&values_request)).await?;
+        let keys: js_sys::Array = keys_request.result()?.unchecked_into();
+        let values: js_sys::Array = values_request.result()?.unchecked_into();
+        Ok(keys
+            .iter()
+            .zip(values.iter())
+            .filter_map(|(key, value)| Some((key.as_string()?, Stored::from_js(&value)?)))
+            .collect())
+    }
+
+    fn store(&self, mode: IdbTransactionMode) -> Result<IdbObjectStore, JsValue> {
+        self.db
+            .transaction_with_str_and_mode(STORE_NAME, mode)?
+            .object_store(STORE_NAME)
+    }
+
+    fn track(&self, request: IdbRequest) {
+        self.pending.borrow_mut().count += 1;
+        let pending = self.pending.clone();
+        // Only one of the handlers is ever called, which frees the closure.
+        let done = Closure::once_into_js(move |event: web_sys::Event| {
+            let error = (event.type_() == "error").then(|| "cannot store changes".to_string());
+            pending.borrow_mut().done(error);
+        });
+        request.set_onsuccess(Some(done.unchecked_ref()));
+        request.set_onerror(Some(done.unchecked_ref()));
+    }
+
+    pub fn put(&self, key: &str, stored: &Stored) -> std::io::Result<()> {
+        let cannot_store = |_| std::io::Error::from(StorageError::backend("cannot store value"));
+        let value = stored.to_js().map_err(cannot_store)?;
+        let request = self
+            .store(IdbTransactionMode::Readwrite)
+            .and_then(|store| store.put_with_key(&value, &key.into()))
+            .map_err(cannot_store)?;
+        self.track(request);
+        Ok(())
+    }
+
+    pub fn delete(&self, key: &str) -> std::io::Result<()> {
+        let request = self
+            .store(IdbTransactionMode::Readwrite)
+            .and_then(|store| store.delete(&key.into()))
+            .map_err(|_| std::io::Error::from(StorageError::backend("cannot remove value")))?;
+        self.track(request);
+        Ok(())
+    }
+
+    /// Wait until all changes so far have completed, reporting the first failure since the last
+    /// flush.
+    pub async fn flush(&self) -> std::io::Result<()> {
+        if self.pending.borrow().count > 0 {
+            let done = Promise::new(&mut |resolve, _| {
+                self.pending.borrow_mut().waiting.push(resolve);
+            });
+            let _ = JsFuture::from(done).await;
+        }
+        match self.pending.borrow_mut().error.take() {
+            Some(error) => Err(StorageError::backend(error).into()),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,4 @@
+mod database;
+mod storage;
+
+pub use storage::IndexedDbStorage;
@@ -0,0 +1,493 @@
+use super::super::web::space;
+use super::super::{Category, ChangeKind, Entry, Metadata, Space, Watcher};
+use super::database::{Database, IndexedDbAvailabilityError, Stored};
+use crate::storage::error::StorageError;
+use crate::storage::generic::{finished_writer, Commit};
+use crate::storage::keys::{entries_under, join};
+use crate::storage::name::InvalidNameError;
+use crate::storage::transaction::RecoverOnOpen;
+use crate::storage::watch::{Notifier, Scope};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+static SEP: char = '/';
+
+/// Everything in the database is kept in memory, so that it can be accessed synchronously.
+struct Shared {
+    db: Database,
+    files: RefCell<HashMap<String, Stored>>,
+    /// Other tabs do not tell us about their changes, so only changes from this one are seen.
+    notifier: Notifier,
+}
+
+impl Shared {
+    fn store(&self, key: &str, contents: Vec<u8>) -> std::io::Result<()> {
+        let stored = Stored::new(contents);
+        self.db.put(key, &stored)?;
+        let kind = match self.files.borrow_mut().insert(key.to_string(), stored) {
+            Some(_) => ChangeKind::Modified,
+            None => ChangeKind::Created,
+        };
+        self.notifier.notify(key, kind)
+    }
+
+    fn remove(&self, key: &str) -> std::io::Result<()> {
+        self.db.delete(key)?;
+        if self.files.borrow_mut().remove(key).is_some() {
+            self.notifier.notify(key, ChangeKind::Removed)?;
+        }
+        Ok(())
+    }
+
+    /// Move a file to another key, keeping its contents and modification time.
+    fn rename(&self, from: &str, to: &str) -> std::io::Result<()> {
+        let mut files = self.files.borrow_mut();
+        let stored = files.get(from).ok_or_else(Self::not_found)?;
+        if from == to {
+            return Ok(());
+        }
+        self.db.put(to, stored)?;
+        self.db.delete(from)?;
+        let stored = files.remove(from).expect("file should have been found");
+        let kind = match files.insert(to.to_string(), stored) {
+            Some(_) => ChangeKind::Modified,
+            None => ChangeKind::Created,
+        };
+        drop(files);
+        self.notifier.notify(from, ChangeKind::Removed)?;
+        self.notifier.notify(to, kind)
+    }
+
+    fn not_found() -> std::io::Error {
+        std::io::Error::from(StorageError::NotFound)
+    }
+}
+
+/// Collects contents which replace the stored file when committed.
+pub struct StorageWriter {
+    shared: Rc<Shared>,
+    key: String,
+    buf: Option<Vec<u8>>,
+}
+
+impl Write for StorageWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf.as_mut().ok_or_else(finished_writer)?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Commit for StorageWriter {
+    fn commit(&mut self) -> std::io::Result<()> {
+        let buf = self.buf.take().ok_or_else(finished_writer)?;
+        self.shared.store(&self.key, buf)
+    }
+
+    fn abort(&mut self) {
+        self.buf = None;
+    }
+}
+
+pub struct ReadOnly;
+pub struct ReadWrite;
+
+pub struct File<R> {
+    _phantom: PhantomData<R>,
+    shared: Rc<Shared>,
+    path: Result<String, InvalidNameError>,
+}
+
+impl<R> File<R> {
+    fn new(
+        parent_path: &Result<String, InvalidNameError>,
+        name: Cow<'static, str>,
+        shared: Rc<Shared>,
+    ) -> Self {
+        Self {
+            _phantom: PhantomData,
+            shared,
+            path: join(parent_path, &name),
+        }
+    }
+
+    fn path(&self) -> std::io::Result<&String> {
+        self.path.as_ref().map_err(|e| e.clone().into())
+    }
+
+    fn read(&self) -> std::io::Result<Cursor<Vec<u8>>> {
+        let files = self.shared.files.borrow();
+        let stored = files.get(self.path()?).ok_or_else(Shared::not_found)?;
+        Ok(Cursor::new(stored.contents.clone()))
+    }
+
+    fn writer(&self) -> std::io::Result<StorageWriter> {
+        Ok(StorageWriter {
+            shared: self.shared.clone(),
+            key: self.path()?.clone(),
+            buf: Some(Vec::new()),
+        })
+    }
+}
+
+impl<R> super::super::File for File<R> {
+    type ReadText = Cursor<Vec<u8>>;
+    type ReadBinary = Cursor<Vec<u8>>;
+
+    fn exists(&self) -> std::io::Result<bool> {
+        Ok(self.shared.files.borrow().contains_key(self.path()?))
+    }
+
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        let files = self.shared.files.borrow();
+        let stored = files.get(self.path()?).ok_or_else(Shared::not_found)?;
+        Ok(Metadata {
+            len: stored.contents.len() as u64,
+            modified: Some(stored.modified),
+        })
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.shared
+            .notifier
+            .watch(Scope::File(self.path()?.clone()))
+    }
+
+    fn read_text(&self) -> std::io::Result<Self::ReadText> {
+        self.read()
+    }
+
+    fn read_binary(&self) -> std::io::Result<Self::ReadBinary> {
+        self.read()
+    }
+}
+
+impl super::super::WritableFile for File<ReadWrite> {
+    type WriteText = StorageWriter;
+    type WriteBinary = StorageWriter;
+
+    fn remove(&mut self) -> std::io::Result<()> {
+        self.shared.remove(self.path()?)
+    }
+
+    fn write_text(&mut self) -> std::io::Result<Self::WriteText> {
+        self.writer()
+    }
+
+    fn write_binary(&mut self) -> std::io::Result<Self::WriteBinary> {
+        self.writer()
+    }
+
+    fn location(&self) -> std::io::Result<String> {
+        Ok(format!("{:p}:{}", Rc::as_ptr(&self.shared), self.path()?))
+    }
+
+    fn copy_to(&self, target: &mut Self) -> std::io::Result<()> {
+        if Rc::ptr_eq(&self.shared, &target.shared) && self.path()? == target.path()? {
+            return Ok(());
+        }
+        let contents = self.read()?.into_inner();
+        target.shared.store(target.path()?, contents)
+    }
+
+    fn move_to(&mut self, target: &mut Self) -> std::io::Result<()> {
+        if Rc::ptr_eq(&self.shared, &target.shared) {
+            self.shared.rename(self.path()?, target.path()?)
+        } else {
+            self.copy_to(target)?;
+            self.remove()
+        }
+    }
+}
+
+pub struct Dir<R> {
+    _phantom: PhantomData<R>,
+    shared: Rc<Shared>,
+    path: Result<String, InvalidNameError>,
+}
+
+impl<R> Dir<R> {
+    fn new(
+        parent_path: &Result<String, InvalidNameError>,
+        name: Cow<'static, str>,
+        shared: Rc<Shared>,
+    ) -> Self {
+        let path = join(parent_path, &name).map(|mut path| {
+            path.push(SEP);
+            path
+        });
+        Self {
+            _phantom: PhantomData,
+            shared,
+            path,
+        }
+    }
+
+    fn path(&self) -> std::io::Result<&String> {
+        self.path.as_ref().map_err(|e| e.clone().into())
+    }
+}
+
+impl<R> super::super::Dir for Dir<R> {
+    type File = File<R>;
+
+    fn file(&self, name: Cow<'static, str>) -> Self::File {
+        File::new(&self.path, name, self.shared.clone())
+    }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        let files = self.shared.files.borrow();
+        Ok(entries_under(
+            self.path()?,
+            SEP,
+            files.keys().map(|k| k.as_str()),
+        ))
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.shared.notifier.watch(Scope::Dir(self.path()?.clone()))
+    }
+}
+
+impl<R> super::super::ParentDir for Dir<R> {
+    type LeafDir = Dir<R>;
+
+    fn subdir(&self, name: Cow<'static, str>) -> Self {
+        Self::new(&self.path, name, self.shared.clone())
+    }
+
+    fn into_leaf(self) -> Self::LeafDir {
+        self
+    }
+}
+
+impl super::super::WritableDir for Dir<ReadWrite> {
+    type WritableFile = File<ReadWrite>;
+
+    fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
+        File::new(&self.path, name, self.shared.clone())
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        let path = self.path()?;
+        let keys: Vec<_> = self
+            .shared
+            .files
+            .borrow()
+            .keys()
+            .filter(|k| k.starts_with(path))
+            .cloned()
+            .collect();
+        for key in keys {
+            self.shared.remove(&key)?;
+        }
+        Ok(())
+    }
+}
+
+impl super::super::WritableParentDir for Dir<ReadWrite> {
+    type WritableLeafDir = Dir<ReadWrite>;
+
+    fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self {
+        Self::new(&self.path, name, self.shared.clone())
+    }
+
+    fn into_writable_leaf(self) -> Self::WritableLeafDir {
+        self
+    }
+
+    fn remove_subdir(&mut self, name: Cow<'static, str>) -> std::io::Result<()> {
+        super::super::WritableDir::clear(&mut self.writable_subdir(name))
+    }
+}
+
+/// Storage in an IndexedDB database, for web. Unlike the default web storage this can hold
+/// larger amounts of binary data.
+///
+/// The whole database is loaded into memory when opened, and writes are sent to the database in
+/// the background, so committing a writer does not wait for the database. Use
+/// [IndexedDbStorage::flush] to wait for writes to finish and find out whether they succeeded. The
+/// first failure is kept until the next flush reports it.
+pub struct IndexedDbStorage {
+    shared: Rc<Shared>,
+    recover: RecoverOnOpen,
+}
+
+impl IndexedDbStorage {
+    /// Open the database with the given name, creating it if needed.
+    pub async fn open(name: &str) -> Result<Self, IndexedDbAvailabilityError> {
+        let (db, files) = Database::open(name).await?;
+        Ok(Self {
+            shared: Rc::new(Shared {
+                db,
+                files: RefCell::new(files),
+                notifier: Notifier::default(),
+            }),
+            recover: RecoverOnOpen::default(),
+        })
+    }
+
+    /// Wait until all writes so far are stored in the database. Fails if any write since the last
+    /// flush failed.
+    pub async fn flush(&self) -> std::io::Result<()> {
+        self.shared.db.flush().await
+    }
+
+    fn outer_dir<R>(&self, name: &'static str) -> Dir<R> {
+        Dir::new(&Ok("".to_string()), name.into(), self.shared.clone())
+    }
+}
+
+impl super::super::Storage for IndexedDbStorage {
+    type Dir = Dir<ReadOnly>;
+    type WritableDir = Dir<ReadWrite>;
+
+    fn dir(&self, category: Category) -> Result<Self::Dir, StorageError> {
+        self.recover.open(
+            category,
+            || Ok(self.outer_dir::<ReadWrite>(category.name())),
+        )?;
+        Ok(self.outer_dir(category.name()))
+    }
+
+    fn writable_dir(&mut self, category: Category) -> Result<Self::WritableDir, StorageError> {
+        self.recover.open(
+            category,
+            || Ok(self.outer_dir::<ReadWrite>(category.name())),
+        )?;
+        Ok(self.outer_dir(category.name()))
+    }
+
+    async fn space(&self) -> std::io::Result<Space> {
+        let used = self
+            .shared
+            .files
+            .borrow()
+            .iter()
+            .map(|(path, stored)| (path.len() + stored.contents.len()) as u64)
+            .sum();
+        Ok(Space {
+            used: Some(used),
+            available: space::available().await?,
+        })
+    }
+
+    async fn request_persistence(&mut self) -> std::io::Result<bool> {
+        space::persist().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::generic::tests as generic_tests;
+    use super::super::super::{
+        Dir as _, File as _, Storage as _, WritableDir as _, WritableFile as _,
+    };
+    use super::*;
+    use std::io::Read;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    async fn make_storage(name: &str) -> IndexedDbStorage {
+        IndexedDbStorage::open(&format!("dias-test-{}", name))
+            .await
+            .unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    async fn text_file() {
+        generic_tests::text_file(make_storage("text_file").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn binary_file() {
+        generic_tests::binary_file(make_storage("binary_file").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn file_uniqueness() {
+        generic_tests::file_uniqueness(make_storage("file_uniqueness").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn categories() {
+        generic_tests::categories(make_storage("categories").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn metadata() {
+        generic_tests::metadata(make_storage("metadata").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn missing_file() {
+        generic_tests::missing_file(make_storage("missing_file").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn commit_and_abort() {
+        generic_tests::commit_and_abort(make_storage("commit_and_abort").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn copy_and_move() {
+        generic_tests::copy_and_move(make_storage("copy_and_move").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn removal() {
+        generic_tests::removal(make_storage("removal").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn names() {
+        generic_tests::names(make_storage("names").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn listing() {
+        generic_tests::listing(make_storage("listing").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn watching() {
+        generic_tests::watching(make_storage("watching").await);
+    }
+
+    #[wasm_bindgen_test]
+    async fn space() {
+        generic_tests::space(make_storage("space").await).await;
+    }
+
+    #[wasm_bindgen_test]
+    async fn persistence() {
+        let contents: Vec<u8> = (0..=255).collect();
+        let mut storage = make_storage("persistence").await;
+        let mut write = storage
+            .writable_data()
+            .unwrap()
+            .writable_file("test".into())
+            .write_binary()
+            .unwrap();
+        write.write_all(&contents).unwrap();
+        write.commit().unwrap();
+        storage.flush().await.unwrap();
+
+        let storage = make_storage("persistence").await;
+        let mut got = Vec::new();
+        storage
+            .data()
+            .unwrap()
+            .file("test".into())
+            .read_binary()
+            .unwrap()
+            .read_to_end(&mut got)
+            .unwrap();
+        assert_eq!(got, contents);
+    }
+}
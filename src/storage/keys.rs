@@ -0,0 +1,41 @@
+//! Helpers for backends which store files under flat string keys, with directories represented
+//! as separator-terminated key prefixes.
+
+use crate::storage::generic::Entry;
+use crate::storage::name::{validate_name, InvalidNameError};
+use std::collections::BTreeMap;
+
+/// Join a name onto a key prefix, keeping any earlier error so it can be reported when the key is
+/// used.
+pub fn join(
+    parent: &Result<String, InvalidNameError>,
+    name: &str,
+) -> Result<String, InvalidNameError> {
+    let mut key = parent.clone()?;
+    validate_name(name)?;
+    key.push_str(name);
+    Ok(key)
+}
+
+/// Find the entries directly inside the directory `prefix` (which should end with `sep`) given
+/// all keys in the store.
+pub fn entries_under<'a>(
+    prefix: &str,
+    sep: char,
+    keys: impl IntoIterator<Item = &'a str>,
+) -> Vec<Entry> {
+    let mut found = BTreeMap::new();
+    for key in keys {
+        if let Some(rest) = key.strip_prefix(prefix) {
+            let entry = match rest.split_once(sep) {
+                Some((dir_name, _)) => Entry::dir(dir_name),
+                None => Entry::file(rest),
+            };
+            // Names we could not have created ourselves, including transaction files, are skipped.
+            if validate_name(&entry.name).is_ok() {
+                found.insert(entry.name.clone(), entry);
+            }
+        }
+    }
+    found.into_values().collect()
+}
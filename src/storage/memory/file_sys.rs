@@ -1,5 +1,11 @@
-use std::collections::HashMap;
+use crate::storage::error::poisoned;
+use crate::storage::error::StorageError;
+use crate::storage::generic::{ChangeKind, Watcher};
+use crate::storage::time::now;
+use crate::storage::watch::{Notifier, Scope};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::SystemTime;
 
 pub struct Shared<T> {
     value: Arc<RwLock<T>>,
@@ -13,15 +19,19 @@ impl<T> Shared<T> {
     }
 
     pub fn read(&self) -> std::io::Result<RwLockReadGuard<'_, T>> {
-        self.value
-            .read()
-            .map_err(|e| std::io::Error::other(e.to_string()))
+        self.value.read().map_err(poisoned)
     }
 
     pub fn write(&self) -> std::io::Result<RwLockWriteGuard<'_, T>> {
-        self.value
-            .write()
-            .map_err(|e| std::io::Error::other(e.to_string()))
+        self.value.write().map_err(poisoned)
+    }
+
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.value, &other.value)
+    }
+
+    pub fn as_ptr(&self) -> *const RwLock<T> {
+        Arc::as_ptr(&self.value)
     }
 }
 
@@ -35,27 +45,82 @@ impl<T> Clone for Shared<T> {
 
 pub struct MemoryFile {
     pub contents: Vec<u8>,
+    pub modified: SystemTime,
 }
 
 impl MemoryFile {
-    fn new() -> Self {
+    fn with_contents(contents: Vec<u8>) -> Self {
         Self {
-            contents: Vec::new(),
+            contents,
+            modified: now(),
         }
     }
 }
 
 pub struct FileSystem {
     contents: HashMap<String, Shared<MemoryFile>>,
+    limit: Option<u64>,
+    notifier: Notifier,
+    locks: HashSet<String>,
 }
 
 impl FileSystem {
-    pub fn new() -> Self {
+    pub fn new(limit: Option<u64>) -> Self {
         Self {
             contents: HashMap::new(),
+            limit,
+            notifier: Notifier::default(),
+            locks: HashSet::new(),
         }
     }
 
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    pub fn used(&self) -> std::io::Result<u64> {
+        let mut used = 0;
+        for file in self.contents.values() {
+            used += file.read()?.contents.len() as u64;
+        }
+        Ok(used)
+    }
+
+    /// Check that the file at a path can be resized to the given length without going over the
+    /// limit.
+    pub fn reserve(&self, path: &str, len: usize) -> std::io::Result<()> {
+        let Some(limit) = self.limit else {
+            return Ok(());
+        };
+        let mut used = len as u64;
+        for (_, other) in self.contents.iter().filter(|(other, _)| *other != path) {
+            used += other.read()?.contents.len() as u64;
+        }
+        if used > limit {
+            return Err(StorageError::QuotaExceeded.into());
+        }
+        Ok(())
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.contents.keys().map(|p| p.as_str())
+    }
+
+    /// Copy the contents of every file.
+    pub fn files(&self) -> std::io::Result<BTreeMap<String, Vec<u8>>> {
+        let mut files = BTreeMap::new();
+        for (path, file) in &self.contents {
+            files.insert(path.clone(), file.read()?.contents.clone());
+        }
+        Ok(files)
+    }
+
+    /// Add a file without notifying watchers, for filling new storage.
+    pub fn insert(&mut self, path: String, contents: Vec<u8>) {
+        self.contents
+            .insert(path, Shared::new(MemoryFile::with_contents(contents)));
+    }
+
     pub fn exists(&self, path: &String) -> bool {
         self.contents.contains_key(path)
     }
@@ -63,20 +128,79 @@ impl FileSystem {
     pub fn get(&self, path: &String) -> std::io::Result<Shared<MemoryFile>> {
         self.contents
             .get(path)
-            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, ""))
+            .ok_or_else(|| std::io::Error::from(StorageError::NotFound))
             .cloned()
     }
 
-    pub fn get_or_create(&mut self, path: &str) -> std::io::Result<Shared<MemoryFile>> {
-        Ok(self
-            .contents
-            .entry(path.to_owned())
-            .or_insert_with(|| Shared::new(MemoryFile::new()))
-            .clone())
+    /// Replace the contents of a file, creating it if needed.
+    pub fn replace(&mut self, path: &str, contents: Vec<u8>) -> std::io::Result<()> {
+        self.reserve(path, contents.len())?;
+        let kind = match self.contents.get(path) {
+            Some(stored) => {
+                let mut stored = stored.write()?;
+                stored.contents = contents;
+                stored.modified = now();
+                ChangeKind::Modified
+            }
+            None => {
+                self.insert(path.to_owned(), contents);
+                ChangeKind::Created
+            }
+        };
+        self.notifier.notify(path, kind)
     }
 
     pub fn remove(&mut self, path: &String) -> std::io::Result<()> {
-        self.contents.remove(path);
+        if self.contents.remove(path).is_some() {
+            self.notifier.notify(path, ChangeKind::Removed)?;
+        }
         Ok(())
     }
+
+    pub fn remove_prefix(&mut self, prefix: &str) -> std::io::Result<()> {
+        let paths: Vec<_> = self
+            .contents
+            .keys()
+            .filter(|path| path.starts_with(prefix))
+            .cloned()
+            .collect();
+        for path in paths {
+            self.remove(&path)?;
+        }
+        Ok(())
+    }
+
+    pub fn rename(&mut self, from: &String, to: &str) -> std::io::Result<()> {
+        if from == to {
+            return self.get(from).map(|_| ());
+        }
+        let stored = self
+            .contents
+            .remove(from)
+            .ok_or_else(|| std::io::Error::from(StorageError::NotFound))?;
+        self.notifier.notify(from, ChangeKind::Removed)?;
+        let kind = match self.contents.insert(to.to_owned(), stored) {
+            Some(_) => ChangeKind::Modified,
+            None => ChangeKind::Created,
+        };
+        self.notifier.notify(to, kind)
+    }
+
+    pub fn watch(&self, scope: Scope) -> std::io::Result<Watcher> {
+        self.notifier.watch(scope)
+    }
+
+    pub fn lock(&mut self, path: &str) -> std::io::Result<()> {
+        if !self.locks.insert(path.to_owned()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::WouldBlock,
+                "file is locked",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn unlock(&mut self, path: &str) {
+        self.locks.remove(path);
+    }
 }
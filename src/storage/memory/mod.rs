@@ -1,5 +1,7 @@
 mod file_sys;
 mod readers_writers;
+mod snapshot;
 mod storage;
 
+pub use snapshot::MemorySnapshot;
 pub use storage::MemoryStorage;
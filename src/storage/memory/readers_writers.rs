@@ -1,4 +1,5 @@
-use crate::storage::memory::file_sys::{MemoryFile, Shared};
+use crate::storage::generic::{finished_writer, Commit};
+use crate::storage::memory::file_sys::{FileSystem, MemoryFile, Shared};
 use std::io::{Cursor, Read, Write};
 
 pub struct StorageReader {
@@ -7,9 +8,7 @@ pub struct StorageReader {
 
 impl StorageReader {
     pub fn new(stored: Shared<MemoryFile>) -> std::io::Result<Self> {
-        let read = stored
-            .read()
-            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        let read = stored.read()?;
         Ok(Self {
             cursor: Cursor::new(read.contents.clone()),
         })
@@ -22,40 +21,46 @@ impl Read for StorageReader {
     }
 }
 
+/// Collects contents which replace the file when committed.
 pub struct StorageWriter {
-    stored: Shared<MemoryFile>,
-    buf: Vec<u8>,
+    fs: Shared<FileSystem>,
+    path: String,
+    buf: Option<Vec<u8>>,
 }
 
 impl StorageWriter {
-    pub fn new(stored: Shared<MemoryFile>) -> std::io::Result<Self> {
-        Ok(Self {
-            stored,
-            buf: Vec::new(),
-        })
+    pub fn new(fs: Shared<FileSystem>, path: &str) -> Self {
+        Self {
+            fs,
+            path: path.to_owned(),
+            buf: Some(Vec::new()),
+        }
+    }
+
+    fn buf(&mut self) -> std::io::Result<&mut Vec<u8>> {
+        self.buf.as_mut().ok_or_else(finished_writer)
     }
 }
 
 impl Write for StorageWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.buf.write(buf)
+        self.buf()?.write(buf)
     }
 
+    /// Check that the contents so far would fit, so that running out of space is found early.
     fn flush(&mut self) -> std::io::Result<()> {
-        let contents = &mut self
-            .stored
-            .write()
-            .map_err(|e| std::io::Error::other(e.to_string()))?
-            .contents;
-        contents.clear();
-        contents.extend(&self.buf);
-        self.buf.clear();
-        Ok(())
+        let len = self.buf()?.len();
+        self.fs.read()?.reserve(&self.path, len)
     }
 }
 
-impl Drop for StorageWriter {
-    fn drop(&mut self) {
-        let _ = self.flush();
+impl Commit for StorageWriter {
+    fn commit(&mut self) -> std::io::Result<()> {
+        let buf = self.buf.take().ok_or_else(finished_writer)?;
+        self.fs.write()?.replace(&self.path, buf)
+    }
+
+    fn abort(&mut self) {
+        self.buf = None;
     }
 }
@@ -0,0 +1,211 @@
+use crate::storage::generic::Category;
+use crate::storage::name::validate_name;
+use std::collections::{BTreeMap, BTreeSet};
+
+static SEP: char = '/';
+
+/// The full contents of a [super::MemoryStorage], for checking exactly what tests wrote and for
+/// starting tests from fixtures.
+///
+/// Files are keyed by their path from the top of storage, starting with the category name and
+/// separated by `/`, such as `data/levels/1`. Modification times are not included, and neither are
+/// the files of transactions which are in progress.
+///
+/// ```rust
+/// use std::io::Write;
+/// use dias::storage::{Commit, MemorySnapshot, MemoryStorage, Storage, WritableDir, WritableFile};
+///
+/// let fixture = MemorySnapshot::from_iter([("config/settings", "volume = 5")]);
+/// let mut storage = MemoryStorage::from_snapshot(fixture).unwrap();
+/// let mut file = storage.writable_data().unwrap().writable_file("save".into());
+/// let mut write = file.write_text().unwrap();
+/// write.write_all(b"level 2").unwrap();
+/// write.commit().unwrap();
+/// assert_eq!(
+///     storage.snapshot().unwrap(),
+///     MemorySnapshot::from_iter([("config/settings", "volume = 5"), ("data/save", "level 2")])
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemorySnapshot {
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+impl MemorySnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check that every file can be reached, so that every path is a category name followed by
+    /// at least one valid name, and no path is both a file and a directory.
+    pub fn validate(&self) -> std::io::Result<()> {
+        let invalid = |path: &str, problem: &str| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} {}", path, problem),
+            )
+        };
+        let mut dirs = BTreeSet::new();
+        for path in self.files.keys() {
+            let mut names = path.split(SEP);
+            let category = names.next().unwrap_or_default();
+            if !Category::ALL.iter().any(|c| c.name() == category) {
+                return Err(invalid(path, "is not in a category"));
+            }
+            let names: Vec<_> = names.collect();
+            let Some((_, dir_names)) = names.split_last() else {
+                return Err(invalid(path, "has no file name"));
+            };
+            for name in &names {
+                validate_name(name)?;
+            }
+            let mut dir = category.to_string();
+            for name in dir_names {
+                dir.push(SEP);
+                dir.push_str(name);
+                dirs.insert(dir.clone());
+            }
+        }
+        match self.files.keys().find(|path| dirs.contains(*path)) {
+            Some(path) => Err(invalid(path, "is both a file and a directory")),
+            None => Ok(()),
+        }
+    }
+
+    /// Read every file under a directory, which is laid out like storage made with
+    /// [crate::storage::StorageBuilder::root].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_dir(root: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let mut snapshot = Self::new();
+        read_dir_into(root.as_ref(), "", &mut snapshot.files)?;
+        Ok(snapshot)
+    }
+
+    /// Write every file under a directory, which can then be used as the root of storage made
+    /// with [crate::storage::StorageBuilder::root]. Existing files are replaced but not removed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn write_dir(&self, root: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        self.validate()?;
+        for (path, contents) in &self.files {
+            let path = root.as_ref().join(path);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, contents)?;
+        }
+        Ok(())
+    }
+}
+
+impl<P: Into<String>, C: Into<Vec<u8>>> FromIterator<(P, C)> for MemorySnapshot {
+    fn from_iter<T: IntoIterator<Item = (P, C)>>(iter: T) -> Self {
+        Self {
+            files: iter
+                .into_iter()
+                .map(|(path, contents)| (path.into(), contents.into()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_dir_into(
+    dir: &std::path::Path,
+    prefix: &str,
+    files: &mut BTreeMap<String, Vec<u8>>,
+) -> std::io::Result<()> {
+    use crate::storage::name::{is_lock_file, is_transaction_file};
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().into_string().map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, "file name is not unicode")
+        })?;
+        if is_lock_file(&name) || is_transaction_file(&name) {
+            continue;
+        }
+        validate_name(&name)?;
+        let path = format!("{}{}", prefix, name);
+        if entry.file_type()?.is_dir() {
+            read_dir_into(&entry.path(), &format!("{}{}", path, SEP), files)?;
+        } else {
+            files.insert(path, std::fs::read(entry.path())?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::storage::{
+        Dir, File, MemoryStorage, Storage, StorageBuilder, WritableDir, WritableFile,
+    };
+    use std::io::Read;
+
+    fn fixture() -> MemorySnapshot {
+        MemorySnapshot::from_iter([
+            ("config/settings", b"volume = 5".to_vec()),
+            ("data/levels/1", vec![0, 1, 2]),
+        ])
+    }
+
+    #[test]
+    fn invalid() {
+        for files in [
+            vec!["data/../secret"],
+            vec!["data"],
+            vec!["saves"],
+            vec!["save/x"],
+            vec!["/data/x"],
+            vec!["data/a", "data/a/b"],
+        ] {
+            let snapshot = MemorySnapshot::from_iter(files.iter().map(|path| (*path, "")));
+            assert_eq!(
+                snapshot.validate().unwrap_err().kind(),
+                std::io::ErrorKind::InvalidInput,
+                "{:?}",
+                files
+            );
+        }
+        MemorySnapshot::from_iter([("saves/x", ""), ("data/a/b", ""), ("data/ab", "")])
+            .validate()
+            .unwrap();
+        let snapshot = MemorySnapshot::from_iter([("data/../secret", "")]);
+        assert!(MemoryStorage::from_snapshot(snapshot.clone()).is_err());
+        let temp = tempfile::tempdir().unwrap();
+        assert!(snapshot.write_dir(temp.path().join("root")).is_err());
+        assert!(!temp.path().join("secret").exists());
+    }
+
+    #[test]
+    fn directories() {
+        let temp = tempfile::tempdir().unwrap();
+        fixture().write_dir(temp.path()).unwrap();
+        let mut storage = StorageBuilder::new("a").root(temp.path()).build().unwrap();
+        let mut text = String::new();
+        storage
+            .config()
+            .unwrap()
+            .file("settings".into())
+            .read_text()
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "volume = 5");
+        let _lock = storage
+            .writable_config()
+            .unwrap()
+            .writable_file("settings".into())
+            .try_lock()
+            .unwrap();
+        assert_eq!(MemorySnapshot::read_dir(temp.path()).unwrap(), fixture());
+    }
+
+    #[cfg(all(feature = "serde", feature = "config"))]
+    #[test]
+    fn serde() {
+        let text = toml::to_string(&fixture()).unwrap();
+        assert_eq!(toml::from_str::<MemorySnapshot>(&text).unwrap(), fixture());
+    }
+}
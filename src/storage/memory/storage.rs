@@ -1,6 +1,11 @@
-use super::super::OuterDirectoryError;
+use super::super::{Category, Entry, FileLock, Metadata, Space, Watcher};
 use super::readers_writers::{StorageReader, StorageWriter};
+use crate::storage::error::StorageError;
+use crate::storage::keys::{entries_under, join};
 use crate::storage::memory::file_sys::{FileSystem, Shared};
+use crate::storage::memory::snapshot::MemorySnapshot;
+use crate::storage::name::{is_transaction_file, InvalidNameError};
+use crate::storage::watch::Scope;
 use std::borrow::Cow;
 use std::marker::PhantomData;
 
@@ -12,19 +17,26 @@ pub struct ReadWrite;
 pub struct File<R> {
     _phantom: PhantomData<R>,
     fs: Shared<FileSystem>,
-    path: String,
+    path: Result<String, InvalidNameError>,
 }
 
 impl<R> File<R> {
-    fn new(parent_path: String, name: Cow<'static, str>, fs: Shared<FileSystem>) -> Self {
-        let mut path = parent_path;
-        path.push_str(name.as_ref());
+    fn new(
+        parent_path: &Result<String, InvalidNameError>,
+        name: Cow<'static, str>,
+        fs: Shared<FileSystem>,
+    ) -> Self {
+        let path = join(parent_path, &name);
         Self {
             _phantom: PhantomData,
             fs,
             path,
         }
     }
+
+    fn path(&self) -> std::io::Result<&String> {
+        self.path.as_ref().map_err(|e| e.clone().into())
+    }
 }
 
 impl<R> super::super::File for File<R> {
@@ -32,19 +44,28 @@ impl<R> super::super::File for File<R> {
     type ReadBinary = StorageReader;
 
     fn exists(&self) -> std::io::Result<bool> {
-        Ok(self
-            .fs
-            .read()
-            .map_err(|e| std::io::Error::other(e.to_string()))?
-            .exists(&self.path))
+        Ok(self.fs.read()?.exists(self.path()?))
+    }
+
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        let stored = self.fs.read()?.get(self.path()?)?;
+        let stored = stored.read()?;
+        Ok(Metadata {
+            len: stored.contents.len() as u64,
+            modified: Some(stored.modified),
+        })
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.fs.read()?.watch(Scope::File(self.path()?.clone()))
     }
 
     fn read_text(&self) -> std::io::Result<Self::ReadText> {
-        StorageReader::new(self.fs.read()?.get(&self.path)?)
+        StorageReader::new(self.fs.read()?.get(self.path()?)?)
     }
 
     fn read_binary(&self) -> std::io::Result<Self::ReadBinary> {
-        StorageReader::new(self.fs.read()?.get(&self.path)?)
+        StorageReader::new(self.fs.read()?.get(self.path()?)?)
     }
 }
 
@@ -53,42 +74,91 @@ impl super::super::WritableFile for File<ReadWrite> {
     type WriteBinary = StorageWriter;
 
     fn remove(&mut self) -> std::io::Result<()> {
-        self.fs.write()?.remove(&self.path)
+        self.fs.write()?.remove(self.path()?)
     }
 
     fn write_text(&mut self) -> std::io::Result<Self::WriteText> {
-        StorageWriter::new(self.fs.write()?.get_or_create(&self.path)?)
+        Ok(StorageWriter::new(self.fs.clone(), self.path()?))
     }
 
     fn write_binary(&mut self) -> std::io::Result<Self::WriteBinary> {
-        StorageWriter::new(self.fs.write()?.get_or_create(&self.path)?)
+        self.write_text()
+    }
+
+    fn location(&self) -> std::io::Result<String> {
+        Ok(format!("{:p}:{}", self.fs.as_ptr(), self.path()?))
+    }
+
+    fn copy_to(&self, target: &mut Self) -> std::io::Result<()> {
+        let contents = self.fs.read()?.get(self.path()?)?.read()?.contents.clone();
+        target.fs.write()?.replace(target.path()?, contents)
+    }
+
+    fn move_to(&mut self, target: &mut Self) -> std::io::Result<()> {
+        if self.fs.ptr_eq(&target.fs) {
+            self.fs.write()?.rename(self.path()?, target.path()?)
+        } else {
+            self.copy_to(target)?;
+            self.remove()
+        }
+    }
+
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        let path = self.path()?.clone();
+        self.fs.write()?.lock(&path)?;
+        let fs = self.fs.clone();
+        Ok(FileLock::new(
+            || Ok(()),
+            move || {
+                if let Ok(mut fs) = fs.write() {
+                    fs.unlock(&path);
+                }
+            },
+        ))
     }
 }
 
 pub struct Dir<R> {
     _phantom: PhantomData<R>,
     fs: Shared<FileSystem>,
-    path: String,
+    path: Result<String, InvalidNameError>,
 }
 
 impl<R> Dir<R> {
-    fn new(parent_path: String, name: Cow<'static, str>, fs: Shared<FileSystem>) -> Self {
-        let mut path = parent_path;
-        path.push_str(name.as_ref());
-        path.push(SEP);
+    fn new(
+        parent_path: &Result<String, InvalidNameError>,
+        name: Cow<'static, str>,
+        fs: Shared<FileSystem>,
+    ) -> Self {
+        let path = join(parent_path, &name).map(|mut path| {
+            path.push(SEP);
+            path
+        });
         Dir {
             _phantom: PhantomData,
             fs,
             path,
         }
     }
+
+    fn path(&self) -> std::io::Result<&String> {
+        self.path.as_ref().map_err(|e| e.clone().into())
+    }
 }
 
 impl<R> super::super::Dir for Dir<R> {
     type File = File<R>;
 
     fn file(&self, name: Cow<'static, str>) -> Self::File {
-        File::new(self.path.clone(), name, self.fs.clone())
+        File::new(&self.path, name, self.fs.clone())
+    }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        Ok(entries_under(self.path()?, SEP, self.fs.read()?.paths()))
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.fs.read()?.watch(Scope::Dir(self.path()?.clone()))
     }
 }
 
@@ -96,7 +166,7 @@ impl<R> super::super::ParentDir for Dir<R> {
     type LeafDir = Dir<R>;
 
     fn subdir(&self, name: Cow<'static, str>) -> Self {
-        Self::new(self.path.clone(), name, self.fs.clone())
+        Self::new(&self.path, name, self.fs.clone())
     }
 
     fn into_leaf(self) -> Self::LeafDir {
@@ -108,7 +178,11 @@ impl super::super::WritableDir for Dir<ReadWrite> {
     type WritableFile = File<ReadWrite>;
 
     fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
-        File::new(self.path.clone(), name, self.fs.clone())
+        File::new(&self.path, name, self.fs.clone())
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        self.fs.write()?.remove_prefix(self.path()?)
     }
 }
 
@@ -116,12 +190,16 @@ impl super::super::WritableParentDir for Dir<ReadWrite> {
     type WritableLeafDir = Dir<ReadWrite>;
 
     fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self {
-        Self::new(self.path.clone(), name, self.fs.clone())
+        Self::new(&self.path, name, self.fs.clone())
     }
 
     fn into_writable_leaf(self) -> Self::WritableLeafDir {
         self
     }
+
+    fn remove_subdir(&mut self, name: Cow<'static, str>) -> std::io::Result<()> {
+        super::super::WritableDir::clear(&mut self.writable_subdir(name))
+    }
 }
 
 /// Basic implementation of storage in memory, mostly for writing tests against storage.
@@ -138,44 +216,81 @@ impl Default for MemoryStorage {
 impl MemoryStorage {
     pub fn new() -> Self {
         Self {
-            fs: Shared::new(FileSystem::new()),
+            fs: Shared::new(FileSystem::new(None)),
         }
     }
-}
 
-impl super::super::Storage for MemoryStorage {
-    type Dir = Dir<ReadOnly>;
-    type WritableDir = Dir<ReadWrite>;
+    /// Storage which holds at most the given number of bytes, after which writes fail with
+    /// [std::io::ErrorKind::StorageFull].
+    pub fn with_limit(limit: u64) -> Self {
+        Self {
+            fs: Shared::new(FileSystem::new(Some(limit))),
+        }
+    }
 
-    fn data(&self) -> Result<Self::Dir, OuterDirectoryError> {
-        Ok(Dir::new("".to_string(), "data".into(), self.fs.clone()))
+    /// Storage holding the files in a snapshot, such as a fixture for a test. Fails if any path in
+    /// the snapshot is not valid.
+    pub fn from_snapshot(snapshot: MemorySnapshot) -> std::io::Result<Self> {
+        snapshot.validate()?;
+        let mut fs = FileSystem::new(None);
+        for (path, contents) in snapshot.files {
+            fs.insert(path, contents);
+        }
+        Ok(Self {
+            fs: Shared::new(fs),
+        })
     }
 
-    fn config(&self) -> Result<Self::Dir, OuterDirectoryError> {
-        Ok(Dir::new("".to_string(), "config".into(), self.fs.clone()))
+    /// Copy the contents of every file in storage.
+    pub fn snapshot(&self) -> std::io::Result<MemorySnapshot> {
+        let mut files = self.fs.read()?.files()?;
+        files.retain(|path, _| !path.split(SEP).any(is_transaction_file));
+        Ok(MemorySnapshot { files })
     }
+}
+
+impl super::super::Storage for MemoryStorage {
+    type Dir = Dir<ReadOnly>;
+    type WritableDir = Dir<ReadWrite>;
 
-    fn cache(&self) -> Result<Self::Dir, OuterDirectoryError> {
-        Ok(Dir::new("".to_string(), "cache".into(), self.fs.clone()))
+    fn dir(&self, category: Category) -> Result<Self::Dir, StorageError> {
+        Ok(Dir::new(
+            &Ok("".to_string()),
+            category.name().into(),
+            self.fs.clone(),
+        ))
     }
 
-    fn writable_data(&mut self) -> Result<Self::WritableDir, OuterDirectoryError> {
-        Ok(Dir::new("".to_string(), "data".into(), self.fs.clone()))
+    fn writable_dir(&mut self, category: Category) -> Result<Self::WritableDir, StorageError> {
+        Ok(Dir::new(
+            &Ok("".to_string()),
+            category.name().into(),
+            self.fs.clone(),
+        ))
     }
 
-    fn writable_config(&mut self) -> Result<Self::WritableDir, OuterDirectoryError> {
-        Ok(Dir::new("".to_string(), "config".into(), self.fs.clone()))
+    async fn space(&self) -> std::io::Result<Space> {
+        let fs = self.fs.read()?;
+        let used = fs.used()?;
+        Ok(Space {
+            used: Some(used),
+            available: fs.limit().map(|limit| limit.saturating_sub(used)),
+        })
     }
 
-    fn writable_cache(&mut self) -> Result<Self::WritableDir, OuterDirectoryError> {
-        Ok(Dir::new("".to_string(), "cache".into(), self.fs.clone()))
+    async fn request_persistence(&mut self) -> std::io::Result<bool> {
+        Ok(false)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::super::generic::tests as generic_tests;
+    use super::super::super::{
+        Commit as _, File as _, Storage as _, WritableDir as _, WritableFile as _,
+    };
     use super::*;
+    use std::io::Write;
 
     fn make_storage() -> MemoryStorage {
         MemoryStorage::new()
@@ -195,4 +310,91 @@ mod tests {
     fn file_uniqueness() {
         generic_tests::file_uniqueness(make_storage());
     }
+
+    #[test]
+    fn categories() {
+        generic_tests::categories(make_storage());
+    }
+
+    #[test]
+    fn locking() {
+        generic_tests::locking(make_storage());
+    }
+
+    #[test]
+    fn metadata() {
+        generic_tests::metadata(make_storage());
+    }
+
+    #[test]
+    fn missing_file() {
+        generic_tests::missing_file(make_storage());
+    }
+
+    #[test]
+    fn commit_and_abort() {
+        generic_tests::commit_and_abort(make_storage());
+    }
+
+    #[test]
+    fn copy_and_move() {
+        generic_tests::copy_and_move(make_storage());
+    }
+
+    #[test]
+    fn removal() {
+        generic_tests::removal(make_storage());
+    }
+
+    #[test]
+    fn names() {
+        generic_tests::names(make_storage());
+    }
+
+    #[test]
+    fn listing() {
+        generic_tests::listing(make_storage());
+    }
+
+    #[test]
+    fn space() {
+        pollster::block_on(generic_tests::space(make_storage()));
+    }
+
+    #[test]
+    fn watching() {
+        generic_tests::watching(make_storage());
+    }
+
+    #[test]
+    fn limit() {
+        let mut storage = MemoryStorage::with_limit(10);
+        let mut dir = storage.writable_data().unwrap();
+        let mut file = dir.writable_file("a".into());
+        let mut write = file.write_binary().unwrap();
+        write.write_all(&[1; 8]).unwrap();
+        write.flush().unwrap();
+        write.write_all(&[1; 2]).unwrap();
+        write.flush().unwrap();
+        write.write_all(&[1]).unwrap();
+        let error = write.flush().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::StorageFull);
+        let error = write.commit().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::StorageFull);
+        drop(write);
+        assert!(!file.exists().unwrap());
+        generic_tests::write_binary(&mut file, &[1; 10]);
+        let mut other = dir.writable_file("b".into());
+        let mut write = other.write_binary().unwrap();
+        write.write_all(&[1]).unwrap();
+        assert!(write.commit().is_err());
+        drop(write);
+        assert_eq!(
+            pollster::block_on(storage.space()).unwrap(),
+            Space {
+                used: Some(10),
+                available: Some(0),
+            }
+        );
+    }
 }
@@ -2,25 +2,66 @@
 //!
 //! - Standard: uses files. Standard locations are provided by the [directories] crate and should
 //!   cover desktop XDG, macOS, and Windows standards.
-//! - Web: uses web storage.
+//! - Web: uses web storage. Storage in IndexedDB is also available through `IndexedDbStorage`, for
+//!   larger amounts of data.
+//!
+//! Any storage can be wrapped in `compressed::CompressedStorage` to compress file contents (with the
+//! `compression` feature), or in `signed::SignedStorage` to detect files edited outside of storage
+//! (with the `signing` feature). Directories can be layered
+//! over each other with [overlay::OverlayDir]. For testing error handling, [faulty::FaultyStorage]
+//! makes any storage fail on demand, and [traced::TracedStorage] records everything done to it.
+//!
+//! Whole trees can be copied between backends with [copy].
+//!
+//! The [asynchronous] traits give the same storage for code which awaits it, such as loading
+//! screens.
+//!
+//! File and directory names must be single path components which are valid on every platform (see
+//! [validate_name]). Using any other name gives an [std::io::ErrorKind::InvalidInput] error.
+//!
+//! Every backend reports the same problem the same way. Errors can be turned into a
+//! [StorageError] to tell them apart, such as a missing file from a full disk.
 //!
 //! ```rust
 //! use std::io::{Read, Write};
-//! use dias::storage::{make_storage, Storage, Dir, File, WritableDir, WritableFile};
+//! use dias::storage::{make_storage, Commit, Storage, Dir, File, WritableDir, WritableFile};
 //!
 //! let mut file = make_storage("Bar App", Some("Foo Corp"), Some("com"))
 //!     .unwrap()
 //!     .writable_data()
 //!     .unwrap()
 //!     .writable_file("test".into());
-//! write!(file.write_text().unwrap(), "hello world");
+//! let mut write = file.write_text().unwrap();
+//! write!(write, "hello world").unwrap();
+//! write.commit().unwrap();
 //! let mut read = String::new();
 //! file.read_text().unwrap().read_to_string(&mut read).unwrap();
 //! ```
 
+#[cfg(feature = "zip")]
+pub mod archive;
+pub mod asynchronous;
 pub mod boxable;
+mod builder;
+#[cfg(feature = "compression")]
+pub mod compressed;
+pub mod copy;
+pub mod embedded;
+mod error;
+pub mod faulty;
 mod generic;
+#[cfg(target_arch = "wasm32")]
+mod indexed_db;
+mod keys;
 mod memory;
+mod name;
+pub mod overlay;
+#[cfg(feature = "signing")]
+pub mod signed;
+mod time;
+pub mod traced;
+pub mod transaction;
+mod watch;
 
 #[cfg(not(target_arch = "wasm32"))]
 mod standard;
@@ -28,15 +69,29 @@ mod standard;
 mod web;
 
 use crate::AvailabilityError;
-use std::error::Error;
 
+pub use builder::StorageBuilder;
+pub use error::StorageError;
+#[allow(deprecated)]
+pub use generic::OuterDirectoryError;
 pub use generic::{
-    Dir, File, OuterDirectoryError, ParentDir, Storage, WritableDir, WritableFile,
-    WritableParentDir,
+    Category, Change, ChangeKind, Commit, Dir, Entry, EntryKind, File, FileLock, Metadata,
+    ParentDir, Space, Storage, Watcher, WritableDir, WritableFile, WritableParentDir,
 };
-pub use memory::MemoryStorage;
+#[cfg(target_arch = "wasm32")]
+pub use indexed_db::IndexedDbStorage;
+pub use memory::{MemorySnapshot, MemoryStorage};
+pub use name::{validate_name, InvalidNameError};
+
+/// The storage for the current platform, as given by [make_storage] and [StorageBuilder].
+#[cfg(not(target_arch = "wasm32"))]
+pub type PlatformStorage = standard::Storage;
+/// The storage for the current platform, as given by [make_storage] and [StorageBuilder].
+#[cfg(target_arch = "wasm32")]
+pub type PlatformStorage = web::Storage;
 
-/// Get a storage object if available.
+/// Get a storage object if available, in the standard location for the platform. Use
+/// [StorageBuilder] to put storage somewhere else.
 ///
 /// The arguments are for uniqueness on various platforms.
 ///
@@ -51,14 +106,9 @@ pub fn make_storage(
     application: &str,
     organization: Option<&str>,
     qualifier: Option<&str>,
-) -> Result<impl Storage, AvailabilityError> {
-    let qualifier = qualifier.unwrap_or("");
-    let organization = organization.unwrap_or("");
-    let _ = (qualifier, organization, application);
-    #[cfg(not(target_arch = "wasm32"))]
-    return standard::Storage::new(qualifier, organization, application)
-        .map_err(|e| AvailabilityError::NotAvailable(Some(Box::new(e) as Box<dyn Error>)));
-    #[cfg(target_arch = "wasm32")]
-    return web::Storage::new()
-        .map_err(|e| AvailabilityError::NotAvailable(Some(Box::new(e) as Box<dyn Error>)));
+) -> Result<PlatformStorage, AvailabilityError> {
+    StorageBuilder::new(application)
+        .organization(organization.unwrap_or(""))
+        .qualifier(qualifier.unwrap_or(""))
+        .build()
 }
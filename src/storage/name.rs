@@ -0,0 +1,181 @@
+//! Names of files and directories. All backends accept exactly the same names, which are the ones
+//! that can be stored as a single path component on every supported platform.
+
+use crate::storage::error::StorageError;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+
+/// Characters which are not allowed anywhere in a name.
+static FORBIDDEN_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Names reserved for devices on Windows, with or without an extension.
+static RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Lock files which standard storage keeps next to locked files, named like `.save.dias-lock`.
+static LOCK_PREFIX: &str = ".";
+static LOCK_SUFFIX: &str = ".dias-lock";
+
+/// Whether a name is one of the lock files kept by standard storage.
+pub(crate) fn is_lock_file(name: &str) -> bool {
+    name.starts_with(LOCK_PREFIX) && name.ends_with(LOCK_SUFFIX)
+}
+
+/// Files which transactions keep next to the files they change, named like `.dias-txn.save`.
+static TRANSACTION_PREFIX: &str = ".dias-txn";
+
+thread_local! {
+    /// Set while a transaction is working on its own files.
+    static TRANSACTION_FILES: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether a name is one of the files kept by transactions.
+pub(crate) fn is_transaction_file(name: &str) -> bool {
+    name.starts_with(TRANSACTION_PREFIX)
+}
+
+/// Run `f` with transaction files accepted as names, and so listed with the other files, on the
+/// current thread. Only for [crate::storage::transaction].
+pub(crate) fn with_transaction_files<T>(f: impl FnOnce() -> T) -> T {
+    struct Restore(bool);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            TRANSACTION_FILES.set(self.0);
+        }
+    }
+    let _restore = Restore(TRANSACTION_FILES.replace(true));
+    f()
+}
+
+/// Name of the lock file for a file with the given name.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn lock_file_name(name: &str) -> String {
+    format!("{}{}{}", LOCK_PREFIX, name, LOCK_SUFFIX)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidNameError {
+    Empty,
+    /// The name is `.` or `..`.
+    Relative,
+    /// The name contains `/` or `\`, so would refer to a path rather than a single name.
+    Separator,
+    ForbiddenChar(char),
+    /// The name ends with `.` or a space, which Windows silently strips.
+    Trailing,
+    /// The name is a reserved device name on Windows.
+    Reserved,
+    /// The name is used by storage itself, for lock files or transactions.
+    Internal,
+}
+
+impl fmt::Display for InvalidNameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for InvalidNameError {}
+
+impl From<InvalidNameError> for std::io::Error {
+    fn from(error: InvalidNameError) -> Self {
+        StorageError::InvalidName(error).into()
+    }
+}
+
+/// Check that a name can be used for a file or directory in storage.
+pub fn validate_name(name: &str) -> Result<(), InvalidNameError> {
+    if name.is_empty() {
+        return Err(InvalidNameError::Empty);
+    }
+    if name == "." || name == ".." {
+        return Err(InvalidNameError::Relative);
+    }
+    if name.contains(['/', '\\']) {
+        return Err(InvalidNameError::Separator);
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| c.is_control() || FORBIDDEN_CHARS.contains(c))
+    {
+        return Err(InvalidNameError::ForbiddenChar(c));
+    }
+    if name.ends_with(['.', ' ']) {
+        return Err(InvalidNameError::Trailing);
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        return Err(InvalidNameError::Reserved);
+    }
+    if is_lock_file(name) || (is_transaction_file(name) && !TRANSACTION_FILES.get()) {
+        return Err(InvalidNameError::Internal);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid() {
+        for name in [
+            "a",
+            "save 1.json",
+            ".hidden",
+            "a.b.c",
+            "ünïcode",
+            "CONSOLE",
+            "con1",
+            "..a",
+        ] {
+            assert_eq!(validate_name(name), Ok(()), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        for (name, error) in [
+            ("", InvalidNameError::Empty),
+            (".", InvalidNameError::Relative),
+            ("..", InvalidNameError::Relative),
+            ("a/b", InvalidNameError::Separator),
+            ("/abs", InvalidNameError::Separator),
+            ("../../.bashrc", InvalidNameError::Separator),
+            ("a\\b", InvalidNameError::Separator),
+            ("C:", InvalidNameError::ForbiddenChar(':')),
+            ("a\0", InvalidNameError::ForbiddenChar('\0')),
+            ("a\n", InvalidNameError::ForbiddenChar('\n')),
+            ("what?", InvalidNameError::ForbiddenChar('?')),
+            ("trailing.", InvalidNameError::Trailing),
+            ("trailing ", InvalidNameError::Trailing),
+            ("CON", InvalidNameError::Reserved),
+            ("con.txt", InvalidNameError::Reserved),
+            ("Lpt9.tar.gz", InvalidNameError::Reserved),
+            ("nul .txt", InvalidNameError::Reserved),
+            (".save.dias-lock", InvalidNameError::Internal),
+            (".dias-txn.save", InvalidNameError::Internal),
+            (".dias-txn-committed", InvalidNameError::Internal),
+        ] {
+            assert_eq!(validate_name(name), Err(error), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn transaction_files() {
+        assert_eq!(
+            with_transaction_files(|| validate_name(".dias-txn.save")),
+            Ok(())
+        );
+        assert_eq!(
+            validate_name(".dias-txn.save"),
+            Err(InvalidNameError::Internal)
+        );
+    }
+}
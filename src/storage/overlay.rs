@@ -0,0 +1,511 @@
+//! Directories made by layering several others, such as a directory of player mods over the
+//! shipped assets.
+//!
+//! Files in the top layer hide files with the same name in lower layers, and files in lower
+//! layers are searched in order. Writes always go to the top layer. Removing a file or directory
+//! which is in a lower layer leaves a marker in the top layer which hides it from then on; these
+//! have names starting with `.wh.`, which should not be used for anything else.
+//!
+//! ```rust
+//! use dias::storage::overlay::OverlayDir;
+//! use dias::storage::{Dir, File, MemoryStorage, Storage, WritableParentDir};
+//!
+//! let mut user = MemoryStorage::new();
+//! let assets = MemoryStorage::new();
+//! let mods = user.writable_data().unwrap().writable_subdir("mods".into());
+//! let dir = OverlayDir::new(mods, vec![assets.data().unwrap()]);
+//! assert!(!dir.file("level1.json".into()).exists().unwrap());
+//! ```
+
+use crate::storage::generic::{
+    Change, ChangeKind, Commit, Dir, Entry, EntryKind, File, FileLock, Metadata, ParentDir,
+    Watcher, WritableDir, WritableFile, WritableParentDir,
+};
+use crate::storage::watch::push_change;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+use std::rc::Rc;
+
+static WHITEOUT_PREFIX: &str = ".wh.";
+
+fn whiteout_name(name: &str) -> Cow<'static, str> {
+    format!("{}{}", WHITEOUT_PREFIX, name).into()
+}
+
+fn whited_out(name: &str) -> Option<&str> {
+    name.strip_prefix(WHITEOUT_PREFIX)
+}
+
+/// Markers in the top layer for the directories containing a file or directory, any of which
+/// hides the lower layers.
+struct Mask<F> {
+    whiteout: F,
+    parent: Option<Rc<Mask<F>>>,
+}
+
+fn masked<F: File>(mut mask: &Option<Rc<Mask<F>>>) -> std::io::Result<bool> {
+    while let Some(m) = mask {
+        if m.whiteout.exists()? {
+            return Ok(true);
+        }
+        mask = &m.parent;
+    }
+    Ok(false)
+}
+
+/// Turn changes to markers into changes to the files they hide. Markers being modified does not
+/// mean anything.
+fn unmask(change: Change) -> Option<Change> {
+    let (dir, name) = match change.name.rsplit_once('/') {
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, change.name.as_str()),
+    };
+    let Some(name) = whited_out(name) else {
+        return Some(change);
+    };
+    let kind = match change.kind {
+        ChangeKind::Created => ChangeKind::Removed,
+        ChangeKind::Removed => ChangeKind::Created,
+        ChangeKind::Modified => return None,
+    };
+    let name = match dir {
+        Some(dir) => format!("{}/{}", dir, name),
+        None => name.to_owned(),
+    };
+    Some(Change::new(name, kind))
+}
+
+/// Watch all layers at once. Changes to files hidden by higher layers are reported too.
+fn watch_layers(mut watchers: Vec<Watcher>) -> Watcher {
+    Watcher::new(move || {
+        let mut changes = Vec::new();
+        for watcher in &mut watchers {
+            for change in watcher.changes()?.into_iter().filter_map(unmask) {
+                push_change(&mut changes, &change.name, change.kind);
+            }
+        }
+        Ok(changes)
+    })
+}
+
+pub enum OverlayRead<T, L> {
+    Top(T),
+    Lower(L),
+}
+
+impl<T: Read, L: Read> Read for OverlayRead<T, L> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Top(read) => read.read(buf),
+            Self::Lower(read) => read.read(buf),
+        }
+    }
+}
+
+pub struct OverlayFile<F, M, L> {
+    top: F,
+    whiteout: F,
+    masks: Option<Rc<Mask<M>>>,
+    lower: Vec<L>,
+}
+
+impl<F: File, M: File, L: File> OverlayFile<F, M, L> {
+    /// The file in a lower layer which is seen when there is none in the top layer.
+    fn visible_lower(&self) -> std::io::Result<Option<&L>> {
+        if self.whiteout.exists()? || masked(&self.masks)? {
+            return Ok(None);
+        }
+        for lower in &self.lower {
+            if lower.exists()? {
+                return Ok(Some(lower));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Read from whichever layer can be seen. If none can then the top layer gives the error.
+    fn read<T, R>(
+        &self,
+        read_top: impl FnOnce(&F) -> std::io::Result<T>,
+        read_lower: impl FnOnce(&L) -> std::io::Result<R>,
+    ) -> std::io::Result<OverlayRead<T, R>> {
+        if !self.top.exists()? {
+            if let Some(lower) = self.visible_lower()? {
+                return read_lower(lower).map(OverlayRead::Lower);
+            }
+        }
+        read_top(&self.top).map(OverlayRead::Top)
+    }
+}
+
+impl<F: File, M: File, L: File> File for OverlayFile<F, M, L> {
+    type ReadText = OverlayRead<F::ReadText, L::ReadText>;
+    type ReadBinary = OverlayRead<F::ReadBinary, L::ReadBinary>;
+
+    fn exists(&self) -> std::io::Result<bool> {
+        Ok(self.top.exists()? || self.visible_lower()?.is_some())
+    }
+
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        match self.read(F::metadata, L::metadata)? {
+            OverlayRead::Top(metadata) | OverlayRead::Lower(metadata) => Ok(metadata),
+        }
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        let mut watchers = vec![self.top.watch()?, self.whiteout.watch()?];
+        for lower in &self.lower {
+            watchers.push(lower.watch()?);
+        }
+        Ok(watch_layers(watchers))
+    }
+
+    fn read_text(&self) -> std::io::Result<Self::ReadText> {
+        self.read(F::read_text, L::read_text)
+    }
+
+    fn read_binary(&self) -> std::io::Result<Self::ReadBinary> {
+        self.read(F::read_binary, L::read_binary)
+    }
+}
+
+impl<F: WritableFile, M: File, L: File> WritableFile for OverlayFile<F, M, L> {
+    type WriteText = F::WriteText;
+    type WriteBinary = F::WriteBinary;
+
+    fn remove(&mut self) -> std::io::Result<()> {
+        let in_lower = self.visible_lower()?.is_some();
+        if self.top.exists()? || !in_lower {
+            self.top.remove()?;
+        }
+        if in_lower {
+            self.whiteout.write_binary()?.commit()?;
+        }
+        Ok(())
+    }
+
+    fn write_text(&mut self) -> std::io::Result<Self::WriteText> {
+        self.top.write_text()
+    }
+
+    fn write_binary(&mut self) -> std::io::Result<Self::WriteBinary> {
+        self.top.write_binary()
+    }
+
+    fn location(&self) -> std::io::Result<String> {
+        self.top.location()
+    }
+
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        self.top.try_lock()
+    }
+}
+
+/// A directory made of a top layer and lower layers, searched from the start of the list.
+pub struct OverlayDir<T: Dir, L> {
+    top: T,
+    lower: Vec<L>,
+    masks: Option<Rc<Mask<T::File>>>,
+}
+
+impl<T: Dir, L: Dir> OverlayDir<T, L> {
+    pub fn new(top: T, lower: Vec<L>) -> Self {
+        Self {
+            top,
+            lower,
+            masks: None,
+        }
+    }
+
+    fn file_in_layers<F>(
+        &self,
+        name: Cow<'static, str>,
+        top: F,
+        whiteout: F,
+    ) -> OverlayFile<F, T::File, L::File> {
+        OverlayFile {
+            top,
+            whiteout,
+            masks: self.masks.clone(),
+            lower: self.lower.iter().map(|l| l.file(name.clone())).collect(),
+        }
+    }
+
+    /// Names of everything in lower layers which is not hidden by the top layer.
+    fn lower_entries(&self, whiteouts: &HashSet<String>) -> std::io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        if !masked(&self.masks)? {
+            for lower in &self.lower {
+                entries.extend(
+                    lower
+                        .entries()?
+                        .into_iter()
+                        .filter(|e| !whiteouts.contains(&e.name)),
+                );
+            }
+        }
+        Ok(entries)
+    }
+
+    fn subdir_masks(&self, name: &str) -> Option<Rc<Mask<T::File>>> {
+        Some(Rc::new(Mask {
+            whiteout: self.top.file(whiteout_name(name)),
+            parent: self.masks.clone(),
+        }))
+    }
+}
+
+impl<T: Dir, L: Dir> Dir for OverlayDir<T, L> {
+    type File = OverlayFile<T::File, T::File, L::File>;
+
+    fn file(&self, name: Cow<'static, str>) -> Self::File {
+        let top = self.top.file(name.clone());
+        let whiteout = self.top.file(whiteout_name(&name));
+        self.file_in_layers(name, top, whiteout)
+    }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        let mut found = BTreeMap::new();
+        let mut whiteouts = HashSet::new();
+        for entry in self.top.entries()? {
+            match whited_out(&entry.name) {
+                Some(name) => {
+                    whiteouts.insert(name.to_owned());
+                }
+                None => {
+                    found.insert(entry.name.clone(), entry);
+                }
+            }
+        }
+        for entry in self.lower_entries(&whiteouts)? {
+            found.entry(entry.name.clone()).or_insert(entry);
+        }
+        Ok(found.into_values().collect())
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        let mut watchers = vec![self.top.watch()?];
+        for lower in &self.lower {
+            watchers.push(lower.watch()?);
+        }
+        Ok(watch_layers(watchers))
+    }
+}
+
+impl<T: WritableDir, L: Dir> WritableDir for OverlayDir<T, L> {
+    type WritableFile = OverlayFile<T::WritableFile, T::File, L::File>;
+
+    fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
+        let top = self.top.writable_file(name.clone());
+        let whiteout = self.top.writable_file(whiteout_name(&name));
+        self.file_in_layers(name, top, whiteout)
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        self.top.clear()?;
+        let names: HashSet<_> = self
+            .lower_entries(&HashSet::new())?
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        for name in names {
+            self.top
+                .writable_file(whiteout_name(&name))
+                .write_binary()?
+                .commit()?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: ParentDir, L: ParentDir> ParentDir for OverlayDir<T, L> {
+    type LeafDir = Self;
+
+    fn subdir(&self, name: Cow<'static, str>) -> Self {
+        Self {
+            top: self.top.subdir(name.clone()),
+            lower: self.lower.iter().map(|l| l.subdir(name.clone())).collect(),
+            masks: self.subdir_masks(&name),
+        }
+    }
+
+    fn into_leaf(self) -> Self::LeafDir {
+        self
+    }
+}
+
+impl<T: WritableParentDir, L: ParentDir> WritableParentDir for OverlayDir<T, L> {
+    type WritableLeafDir = Self;
+
+    fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self {
+        Self {
+            top: self.top.writable_subdir(name.clone()),
+            lower: self.lower.iter().map(|l| l.subdir(name.clone())).collect(),
+            masks: self.subdir_masks(&name),
+        }
+    }
+
+    fn into_writable_leaf(self) -> Self::WritableLeafDir {
+        self
+    }
+
+    fn remove_subdir(&mut self, name: Cow<'static, str>) -> std::io::Result<()> {
+        self.top.remove_subdir(name.clone())?;
+        let in_lower = self
+            .lower_entries(&HashSet::new())?
+            .iter()
+            .any(|e| e.name == name && e.kind == EntryKind::Dir);
+        if in_lower {
+            self.top
+                .writable_file(whiteout_name(&name))
+                .write_binary()?
+                .commit()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, Storage};
+    use std::io::Write;
+
+    fn write(dir: &mut impl WritableDir, name: &'static str, contents: &str) {
+        let mut write = dir.writable_file(name.into()).write_text().unwrap();
+        write.write_all(contents.as_bytes()).unwrap();
+        write.commit().unwrap();
+    }
+
+    fn read(file: &impl File) -> Option<String> {
+        if !file.exists().unwrap() {
+            return None;
+        }
+        let mut got = String::new();
+        file.read_text().unwrap().read_to_string(&mut got).unwrap();
+        Some(got)
+    }
+
+    fn names(dir: &impl Dir) -> Vec<String> {
+        dir.entries().unwrap().into_iter().map(|e| e.name).collect()
+    }
+
+    /// Player mods over two layers of assets.
+    fn layers() -> [MemoryStorage; 3] {
+        let mut layers = [(); 3].map(|_| MemoryStorage::new());
+        let [mods, assets, base] = &mut layers;
+        let mut mods = mods.writable_data().unwrap().writable_subdir("mods".into());
+        write(&mut mods, "a", "mods a");
+        write(&mut assets.writable_data().unwrap(), "a", "assets a");
+        write(&mut assets.writable_data().unwrap(), "b", "assets b");
+        let mut sub = assets
+            .writable_data()
+            .unwrap()
+            .writable_subdir("sub".into());
+        write(&mut sub, "x", "assets x");
+        write(&mut base.writable_data().unwrap(), "b", "base b");
+        write(&mut base.writable_data().unwrap(), "c", "base c");
+        layers
+    }
+
+    fn overlay(
+        layers: &mut [MemoryStorage; 3],
+    ) -> OverlayDir<impl WritableParentDir, impl ParentDir> {
+        let [mods, assets, base] = layers;
+        OverlayDir::new(
+            mods.writable_data().unwrap().writable_subdir("mods".into()),
+            vec![assets.data().unwrap(), base.data().unwrap()],
+        )
+    }
+
+    #[test]
+    fn reading() {
+        let mut layers = layers();
+        let dir = overlay(&mut layers);
+        assert_eq!(read(&dir.file("a".into())).as_deref(), Some("mods a"));
+        assert_eq!(read(&dir.file("b".into())).as_deref(), Some("assets b"));
+        assert_eq!(read(&dir.file("c".into())).as_deref(), Some("base c"));
+        assert_eq!(read(&dir.file("d".into())), None);
+        assert_eq!(
+            read(&dir.subdir("sub".into()).file("x".into())).as_deref(),
+            Some("assets x")
+        );
+        assert_eq!(names(&dir), vec!["a", "b", "c", "sub"]);
+        assert_eq!(dir.file("b".into()).metadata().unwrap().len, 8);
+    }
+
+    #[test]
+    fn writing() {
+        let mut layers = layers();
+        let mut dir = overlay(&mut layers);
+        write(&mut dir, "b", "new b");
+        write(&mut dir.writable_subdir("sub".into()), "y", "new y");
+        assert_eq!(read(&dir.file("b".into())).as_deref(), Some("new b"));
+        assert_eq!(names(&dir.subdir("sub".into())), vec!["x", "y"]);
+        drop(dir);
+        let assets = layers[1].data().unwrap();
+        assert_eq!(read(&assets.file("b".into())).as_deref(), Some("assets b"));
+        assert_eq!(names(&assets.subdir("sub".into())), vec!["x"]);
+    }
+
+    #[test]
+    fn renaming() {
+        let mut layers = layers();
+        let mut dir = overlay(&mut layers);
+        for name in ["a", "b"] {
+            dir.rename(name.into(), name.into()).unwrap();
+        }
+        assert_eq!(read(&dir.file("a".into())).as_deref(), Some("mods a"));
+        assert_eq!(read(&dir.file("b".into())).as_deref(), Some("assets b"));
+        dir.rename("b".into(), "d".into()).unwrap();
+        assert_eq!(read(&dir.file("b".into())), None);
+        assert_eq!(read(&dir.file("d".into())).as_deref(), Some("assets b"));
+    }
+
+    #[test]
+    fn removal() {
+        let mut layers = layers();
+        let mut dir = overlay(&mut layers);
+        for name in ["a", "b"] {
+            dir.writable_file(name.into()).remove().unwrap();
+            assert_eq!(read(&dir.file(name.into())), None);
+        }
+        assert_eq!(names(&dir), vec!["c", "sub"]);
+        write(&mut dir, "b", "new b");
+        assert_eq!(read(&dir.file("b".into())).as_deref(), Some("new b"));
+        dir.writable_file("b".into()).remove().unwrap();
+        assert_eq!(read(&dir.file("b".into())), None);
+
+        dir.remove_subdir("sub".into()).unwrap();
+        assert_eq!(names(&dir), vec!["c"]);
+        let mut sub = dir.writable_subdir("sub".into());
+        assert_eq!(read(&sub.file("x".into())), None);
+        write(&mut sub, "y", "new y");
+        assert_eq!(names(&sub), vec!["y"]);
+        assert_eq!(names(&dir), vec!["c", "sub"]);
+
+        dir.clear().unwrap();
+        assert_eq!(names(&dir), Vec::<String>::new());
+        drop(dir);
+        let assets = layers[1].data().unwrap();
+        assert_eq!(names(&assets), vec!["a", "b", "sub"]);
+    }
+
+    #[test]
+    fn watching() {
+        let mut layers = layers();
+        let mut dir = overlay(&mut layers);
+        let mut watcher = dir.watch().unwrap();
+        dir.writable_file("b".into()).remove().unwrap();
+        assert_eq!(
+            watcher.changes().unwrap(),
+            vec![Change::new("b", ChangeKind::Removed)]
+        );
+        write(&mut dir, "c", "new c");
+        assert_eq!(
+            watcher.changes().unwrap(),
+            vec![Change::new("c", ChangeKind::Created)]
+        );
+    }
+}
@@ -0,0 +1,595 @@
+//! Storage which signs file contents, and optionally encrypts them, wrapping any other storage.
+//!
+//! Contents are signed with a key provided by the application, so that files edited or replaced
+//! outside of storage are detected when read. Reading them gives an [std::io::ErrorKind::InvalidData]
+//! error holding a [TamperedError] (see [is_tampered]). This includes files which were written
+//! without signing.
+//!
+//! The key is compiled into the application, so this only keeps out casual editing, not a
+//! determined player. Signatures are not tied to file names, so whole files can still be swapped
+//! around.
+//!
+//! ```rust
+//! use std::io::Write;
+//! use dias::storage::signed::SignedStorage;
+//! use dias::storage::{Commit, MemoryStorage, Storage, WritableDir, WritableFile};
+//!
+//! let mut storage = SignedStorage::new(MemoryStorage::new(), b"not very secret").encrypted();
+//! let mut file = storage.writable_data().unwrap().writable_file("unlocks".into());
+//! let mut write = file.write_text().unwrap();
+//! write!(write, "everything").unwrap();
+//! write.commit().unwrap();
+//! ```
+
+use crate::storage::error::StorageError;
+use crate::storage::generic::{
+    finished_writer, Category, Commit, Dir, Entry, File, FileLock, Metadata, ParentDir, Space,
+    Storage, Watcher, WritableDir, WritableFile, WritableParentDir,
+};
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::io::{Cursor, Read, Write};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Start of signed contents.
+static HEADER: &[u8] = b"\xFFds\x01";
+const MODE_SIGNED: u8 = 0;
+const MODE_ENCRYPTED: u8 = 1;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 32;
+const FRAME_MORE: u8 = 0;
+const FRAME_LAST: u8 = 1;
+
+/// File contents were changed outside of storage, or were not written by signed storage.
+#[derive(Debug)]
+pub struct TamperedError;
+
+impl fmt::Display for TamperedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for TamperedError {}
+
+impl From<TamperedError> for std::io::Error {
+    fn from(error: TamperedError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// Check whether an error from reading a file is because the file was tampered with.
+pub fn is_tampered(error: &std::io::Error) -> bool {
+    error
+        .get_ref()
+        .is_some_and(|error| error.is::<TamperedError>())
+}
+
+fn new_mac(key: &[u8]) -> HmacSha256 {
+    HmacSha256::new_from_slice(key).expect("HMAC should accept keys of any length")
+}
+
+#[derive(Clone)]
+struct Keys {
+    mac: [u8; 32],
+    cipher: [u8; 32],
+    encrypt: bool,
+}
+
+impl Keys {
+    fn new(key: &[u8]) -> Self {
+        let derive = |label: &[u8]| {
+            let mut mac = new_mac(key);
+            mac.update(label);
+            mac.finalize().into_bytes().into()
+        };
+        Self {
+            mac: derive(b"dias signed storage mac"),
+            cipher: derive(b"dias signed storage cipher"),
+            encrypt: false,
+        }
+    }
+
+    fn cipher(&self, nonce: &[u8; NONCE_LEN]) -> ChaCha20 {
+        ChaCha20::new(&self.cipher.into(), nonce.into())
+    }
+
+    /// Tag for a frame, which also covers everything before it through the previous tag.
+    fn tag(&self, previous: &[u8], flag: u8, data: &[u8]) -> HmacSha256 {
+        let mut mac = new_mac(&self.mac);
+        mac.update(previous);
+        mac.update(&[flag]);
+        mac.update(&(data.len() as u32).to_le_bytes());
+        mac.update(data);
+        mac
+    }
+}
+
+/// Check and decode signed contents. Anything unexpected counts as tampering.
+fn open(keys: &Keys, contents: &[u8]) -> Result<Vec<u8>, TamperedError> {
+    fn take<'a>(contents: &mut &'a [u8], len: usize) -> Result<&'a [u8], TamperedError> {
+        if contents.len() < len {
+            return Err(TamperedError);
+        }
+        let (taken, rest) = contents.split_at(len);
+        *contents = rest;
+        Ok(taken)
+    }
+
+    let mut rest = contents;
+    if take(&mut rest, HEADER.len())? != HEADER {
+        return Err(TamperedError);
+    }
+    let mode = take(&mut rest, 1)?[0];
+    let nonce: [u8; NONCE_LEN] = take(&mut rest, NONCE_LEN)?.try_into().unwrap();
+    let mut cipher = match mode {
+        MODE_SIGNED => None,
+        MODE_ENCRYPTED => Some(keys.cipher(&nonce)),
+        _ => return Err(TamperedError),
+    };
+    let mut tag = keys
+        .tag(&[], mode, &contents[..HEADER.len() + 1 + NONCE_LEN])
+        .finalize()
+        .into_bytes()
+        .to_vec();
+    let mut opened = Vec::new();
+    loop {
+        let flag = take(&mut rest, 1)?[0];
+        let len = u32::from_le_bytes(take(&mut rest, 4)?.try_into().unwrap());
+        let data = take(&mut rest, len as usize)?;
+        let frame_tag = take(&mut rest, TAG_LEN)?;
+        keys.tag(&tag, flag, data)
+            .verify_slice(frame_tag)
+            .map_err(|_| TamperedError)?;
+        tag = frame_tag.to_vec();
+        let start = opened.len();
+        opened.extend_from_slice(data);
+        if let Some(cipher) = &mut cipher {
+            cipher.apply_keystream(&mut opened[start..]);
+        }
+        match flag {
+            FRAME_MORE => continue,
+            FRAME_LAST if rest.is_empty() => return Ok(opened),
+            _ => return Err(TamperedError),
+        }
+    }
+}
+
+/// Writes contents in signed frames, one for each flush and a last one when committed, so that
+/// partly written contents cannot be passed off as complete.
+pub struct SignedWrite<W: Write> {
+    write: W,
+    keys: Keys,
+    cipher: Option<ChaCha20>,
+    tag: Vec<u8>,
+    /// Contents since the last frame, until committed or aborted.
+    buf: Option<Vec<u8>>,
+}
+
+impl<W: Write> SignedWrite<W> {
+    fn new(mut write: W, keys: Keys) -> std::io::Result<Self> {
+        let mut nonce = [0; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(|e| StorageError::backend(e.to_string()))?;
+        let mode = if keys.encrypt {
+            MODE_ENCRYPTED
+        } else {
+            MODE_SIGNED
+        };
+        let mut start = HEADER.to_vec();
+        start.push(mode);
+        start.extend_from_slice(&nonce);
+        write.write_all(&start)?;
+        Ok(Self {
+            write,
+            cipher: keys.encrypt.then(|| keys.cipher(&nonce)),
+            tag: keys.tag(&[], mode, &start).finalize().into_bytes().to_vec(),
+            keys,
+            buf: Some(Vec::new()),
+        })
+    }
+
+    fn buf(&mut self) -> std::io::Result<&mut Vec<u8>> {
+        self.buf.as_mut().ok_or_else(finished_writer)
+    }
+
+    fn write_frame(&mut self, flag: u8) -> std::io::Result<()> {
+        let mut data = std::mem::take(self.buf()?);
+        if let Some(cipher) = &mut self.cipher {
+            cipher.apply_keystream(&mut data);
+        }
+        let len: u32 = data
+            .len()
+            .try_into()
+            .map_err(|_| std::io::Error::other("too much data between flushes"))?;
+        self.tag = self
+            .keys
+            .tag(&self.tag, flag, &data)
+            .finalize()
+            .into_bytes()
+            .to_vec();
+        self.write.write_all(&[flag])?;
+        self.write.write_all(&len.to_le_bytes())?;
+        self.write.write_all(&data)?;
+        self.write.write_all(&self.tag)
+    }
+}
+
+impl<W: Write> Write for SignedWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buf()?.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if !self.buf()?.is_empty() {
+            self.write_frame(FRAME_MORE)?;
+        }
+        self.write.flush()
+    }
+}
+
+impl<W: Commit> Commit for SignedWrite<W> {
+    fn commit(&mut self) -> std::io::Result<()> {
+        self.write_frame(FRAME_LAST)?;
+        self.buf = None;
+        self.write.commit()
+    }
+
+    fn abort(&mut self) {
+        self.buf = None;
+        self.write.abort()
+    }
+}
+
+pub struct SignedFile<F> {
+    source: F,
+    keys: Keys,
+}
+
+impl<F: File> SignedFile<F> {
+    fn read(&self) -> std::io::Result<Cursor<Vec<u8>>> {
+        let mut contents = Vec::new();
+        self.source.read_binary()?.read_to_end(&mut contents)?;
+        Ok(Cursor::new(open(&self.keys, &contents)?))
+    }
+}
+
+impl<F: File> File for SignedFile<F> {
+    type ReadText = Cursor<Vec<u8>>;
+    type ReadBinary = Cursor<Vec<u8>>;
+
+    fn exists(&self) -> std::io::Result<bool> {
+        self.source.exists()
+    }
+
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        self.source.metadata()
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.source.watch()
+    }
+
+    fn read_text(&self) -> std::io::Result<Self::ReadText> {
+        self.read()
+    }
+
+    fn read_binary(&self) -> std::io::Result<Self::ReadBinary> {
+        self.read()
+    }
+}
+
+impl<F: WritableFile> WritableFile for SignedFile<F> {
+    type WriteText = SignedWrite<F::WriteBinary>;
+    type WriteBinary = SignedWrite<F::WriteBinary>;
+
+    fn remove(&mut self) -> std::io::Result<()> {
+        self.source.remove()
+    }
+
+    fn write_text(&mut self) -> std::io::Result<Self::WriteText> {
+        SignedWrite::new(self.source.write_binary()?, self.keys.clone())
+    }
+
+    fn write_binary(&mut self) -> std::io::Result<Self::WriteBinary> {
+        SignedWrite::new(self.source.write_binary()?, self.keys.clone())
+    }
+
+    fn location(&self) -> std::io::Result<String> {
+        self.source.location()
+    }
+
+    fn copy_to(&self, target: &mut Self) -> std::io::Result<()> {
+        self.source.copy_to(&mut target.source)
+    }
+
+    fn move_to(&mut self, target: &mut Self) -> std::io::Result<()> {
+        self.source.move_to(&mut target.source)
+    }
+
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        self.source.try_lock()
+    }
+}
+
+pub struct SignedDir<D> {
+    source: D,
+    keys: Keys,
+}
+
+impl<D> SignedDir<D> {
+    fn new(source: D, keys: &Keys) -> Self {
+        Self {
+            source,
+            keys: keys.clone(),
+        }
+    }
+}
+
+impl<D: Dir> Dir for SignedDir<D> {
+    type File = SignedFile<D::File>;
+
+    fn file(&self, name: Cow<'static, str>) -> Self::File {
+        SignedFile {
+            source: self.source.file(name),
+            keys: self.keys.clone(),
+        }
+    }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        self.source.entries()
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        self.source.watch()
+    }
+}
+
+impl<D: WritableDir> WritableDir for SignedDir<D> {
+    type WritableFile = SignedFile<D::WritableFile>;
+
+    fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
+        SignedFile {
+            source: self.source.writable_file(name),
+            keys: self.keys.clone(),
+        }
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        self.source.clear()
+    }
+}
+
+impl<D: ParentDir> ParentDir for SignedDir<D> {
+    type LeafDir = SignedDir<D::LeafDir>;
+
+    fn subdir(&self, name: Cow<'static, str>) -> Self {
+        Self::new(self.source.subdir(name), &self.keys)
+    }
+
+    fn into_leaf(self) -> Self::LeafDir {
+        SignedDir::new(self.source.into_leaf(), &self.keys)
+    }
+}
+
+impl<D: WritableParentDir> WritableParentDir for SignedDir<D> {
+    type WritableLeafDir = SignedDir<D::WritableLeafDir>;
+
+    fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self {
+        Self::new(self.source.writable_subdir(name), &self.keys)
+    }
+
+    fn into_writable_leaf(self) -> Self::WritableLeafDir {
+        SignedDir::new(self.source.into_writable_leaf(), &self.keys)
+    }
+
+    fn remove_subdir(&mut self, name: Cow<'static, str>) -> std::io::Result<()> {
+        self.source.remove_subdir(name)
+    }
+}
+
+pub struct SignedStorage<S> {
+    source: S,
+    keys: Keys,
+}
+
+impl<S: Storage> SignedStorage<S> {
+    pub fn new(source: S, key: &[u8]) -> Self {
+        Self {
+            source,
+            keys: Keys::new(key),
+        }
+    }
+
+    /// Also encrypt contents written from now on. Encrypted files can be read either way.
+    pub fn encrypted(mut self) -> Self {
+        self.keys.encrypt = true;
+        self
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S: Storage> Storage for SignedStorage<S> {
+    type Dir = SignedDir<S::Dir>;
+    type WritableDir = SignedDir<S::WritableDir>;
+
+    fn dir(&self, category: Category) -> Result<Self::Dir, StorageError> {
+        Ok(SignedDir::new(self.source.dir(category)?, &self.keys))
+    }
+
+    fn writable_dir(&mut self, category: Category) -> Result<Self::WritableDir, StorageError> {
+        Ok(SignedDir::new(
+            self.source.writable_dir(category)?,
+            &self.keys,
+        ))
+    }
+
+    async fn space(&self) -> std::io::Result<Space> {
+        self.source.space().await
+    }
+
+    async fn request_persistence(&mut self) -> std::io::Result<bool> {
+        self.source.request_persistence().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::generic::tests as generic_tests;
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    static KEY: &[u8] = b"test key";
+
+    fn make_storage() -> SignedStorage<MemoryStorage> {
+        SignedStorage::new(MemoryStorage::new(), KEY)
+    }
+
+    fn read(file: &impl File) -> std::io::Result<Vec<u8>> {
+        let mut got = Vec::new();
+        file.read_binary()?.read_to_end(&mut got)?;
+        Ok(got)
+    }
+
+    fn raw(storage: &SignedStorage<MemoryStorage>, name: &'static str) -> Vec<u8> {
+        read(&storage.source.data().unwrap().file(name.into())).unwrap()
+    }
+
+    fn set_raw(storage: &mut SignedStorage<MemoryStorage>, name: &'static str, contents: &[u8]) {
+        generic_tests::write_binary(
+            &mut storage
+                .source
+                .writable_data()
+                .unwrap()
+                .writable_file(name.into()),
+            contents,
+        );
+    }
+
+    fn assert_tampered(result: std::io::Result<Vec<u8>>) {
+        match result {
+            Ok(_) => panic!("tampering not detected"),
+            Err(e) => assert!(is_tampered(&e), "{:?}", e),
+        }
+    }
+
+    #[test]
+    fn text_file() {
+        generic_tests::text_file(make_storage());
+        generic_tests::text_file(make_storage().encrypted());
+    }
+
+    #[test]
+    fn binary_file() {
+        generic_tests::binary_file(make_storage());
+        generic_tests::binary_file(make_storage().encrypted());
+    }
+
+    #[test]
+    fn missing_file() {
+        generic_tests::missing_file(make_storage());
+    }
+
+    #[test]
+    fn commit_and_abort() {
+        generic_tests::commit_and_abort(make_storage());
+    }
+
+    #[test]
+    fn copy_and_move() {
+        generic_tests::copy_and_move(make_storage());
+    }
+
+    #[test]
+    fn removal() {
+        generic_tests::removal(make_storage());
+    }
+
+    #[test]
+    fn encrypts() {
+        let mut storage = make_storage().encrypted();
+        let mut file = storage
+            .writable_data()
+            .unwrap()
+            .writable_file("secret".into());
+        generic_tests::write_binary(&mut file, b"plain text");
+        let raw = raw(&storage, "secret");
+        assert!(!raw.windows(5).any(|w| w == b"plain"));
+        assert_eq!(read(&file).unwrap(), b"plain text");
+        // Readable whether or not new contents are encrypted.
+        let storage = SignedStorage::new(storage.into_inner(), KEY);
+        let file = storage.data().unwrap().file("secret".into());
+        assert_eq!(read(&file).unwrap(), b"plain text");
+    }
+
+    #[test]
+    fn several_flushes() {
+        for mut storage in [make_storage(), make_storage().encrypted()] {
+            let mut file = storage
+                .writable_data()
+                .unwrap()
+                .writable_file("flushed".into());
+            let mut write = file.write_binary().unwrap();
+            for part in [&b"one "[..], b"two ", b"", b"three"] {
+                write.write_all(part).unwrap();
+                write.flush().unwrap();
+            }
+            assert!(!file.exists().unwrap());
+            write.commit().unwrap();
+            assert_eq!(read(&file).unwrap(), b"one two three");
+        }
+    }
+
+    #[test]
+    fn detects_tampering() {
+        for mut storage in [make_storage(), make_storage().encrypted()] {
+            generic_tests::write_binary(
+                &mut storage
+                    .writable_data()
+                    .unwrap()
+                    .writable_file("score".into()),
+                b"score 10",
+            );
+            let signed = raw(&storage, "score");
+            let file = storage.data().unwrap().file("score".into());
+            for i in 0..signed.len() {
+                let mut changed = signed.clone();
+                changed[i] ^= 1;
+                set_raw(&mut storage, "score", &changed);
+                assert_tampered(read(&file));
+            }
+            for len in 0..signed.len() {
+                set_raw(&mut storage, "score", &signed[..len]);
+                assert_tampered(read(&file));
+            }
+            set_raw(&mut storage, "score", &[&signed[..], b"extra"].concat());
+            assert_tampered(read(&file));
+            set_raw(&mut storage, "score", b"score 1000");
+            assert_tampered(read(&file));
+            set_raw(&mut storage, "score", &signed);
+            assert_eq!(read(&file).unwrap(), b"score 10");
+        }
+    }
+
+    #[test]
+    fn wrong_key() {
+        let mut storage = make_storage();
+        generic_tests::write_binary(
+            &mut storage
+                .writable_data()
+                .unwrap()
+                .writable_file("score".into()),
+            b"score 10",
+        );
+        let storage = SignedStorage::new(storage.into_inner(), b"other key");
+        assert_tampered(read(&storage.data().unwrap().file("score".into())));
+    }
+}
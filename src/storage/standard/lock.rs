@@ -0,0 +1,32 @@
+//! Advisory locks using OS file locks on a hidden file next to the locked one. The locked file
+//! itself can not be used since atomic writes replace it.
+//!
+//! Lock file names are rejected by [crate::storage::validate_name], so they never clash with stored
+//! files and are left out when listing or watching. They are never removed, even when clearing or
+//! removing their directory, since another process could be about to lock the old one. A removed
+//! directory which still holds lock files is kept, but left out when listing like any other
+//! directory without files in it.
+
+use super::super::name::lock_file_name;
+use super::super::FileLock;
+use std::fs::{create_dir_all, OpenOptions};
+use std::path::{Path, PathBuf};
+
+fn lock_path(path: &Path) -> PathBuf {
+    path.with_file_name(lock_file_name(
+        &path.file_name().unwrap_or_default().to_string_lossy(),
+    ))
+}
+
+pub fn try_lock(path: &Path) -> std::io::Result<FileLock> {
+    if let Some(dir_path) = path.parent() {
+        create_dir_all(dir_path)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path(path))?;
+    fs4::FileExt::try_lock(&file)?;
+    Ok(FileLock::new(|| Ok(()), move || drop(file)))
+}
@@ -1,4 +1,6 @@
+mod lock;
 mod storage;
+mod watch;
 mod write;
 
 pub use storage::Storage;
@@ -1,17 +1,24 @@
-use super::super::OuterDirectoryError;
+use super::super::name::{is_lock_file, validate_name, InvalidNameError};
+use super::super::{Category, Entry, FileLock, Metadata, Space, Watcher};
+use super::lock;
+use super::watch::{watch_dir, watch_file};
 use super::write::FileWrite;
+use crate::storage::error::StorageError;
+use crate::storage::transaction::RecoverOnOpen;
 use atomic_write_file::AtomicWriteFile;
-use directories::ProjectDirs;
+use directories::{ProjectDirs, UserDirs};
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::error::Error;
 use std::fmt;
-use std::fs::{create_dir_all, remove_file};
+use std::fs::{create_dir_all, read_dir, remove_dir, remove_file, rename};
 use std::marker::PhantomData;
-use std::path::{Component, PathBuf};
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 pub enum StandardStorageAvailabilityError {
     UnknownHomeDirectory,
+    UnknownExecutableDirectory,
 }
 
 impl fmt::Display for StandardStorageAvailabilityError {
@@ -22,21 +29,78 @@ impl fmt::Display for StandardStorageAvailabilityError {
 
 impl Error for StandardStorageAvailabilityError {}
 
+/// Join a name onto a path, keeping any earlier error so it can be reported when the path is used.
+fn join(
+    parent: &Result<PathBuf, InvalidNameError>,
+    name: &str,
+) -> Result<PathBuf, InvalidNameError> {
+    let parent = parent.clone()?;
+    validate_name(name)?;
+    Ok(parent.join(name))
+}
+
+/// Whether there are any files under a path which would be listed, so not counting lock files.
+fn has_files(path: &Path) -> std::io::Result<bool> {
+    let dir_entries = match read_dir(path) {
+        Ok(dir_entries) => dir_entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    for dir_entry in dir_entries {
+        let dir_entry = dir_entry?;
+        let Ok(name) = dir_entry.file_name().into_string() else {
+            continue;
+        };
+        if validate_name(&name).is_err() {
+            continue;
+        }
+        let file_type = dir_entry.file_type()?;
+        if file_type.is_file() || (file_type.is_dir() && has_files(&dir_entry.path())?) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Total size of the files under a path, which may not exist yet.
+fn size_under(path: &Path) -> std::io::Result<u64> {
+    let dir_entries = match read_dir(path) {
+        Ok(dir_entries) => dir_entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut size = 0;
+    for dir_entry in dir_entries {
+        let dir_entry = dir_entry?;
+        let file_type = dir_entry.file_type()?;
+        if file_type.is_dir() {
+            size += size_under(&dir_entry.path())?;
+        } else if file_type.is_file() {
+            size += dir_entry.metadata()?.len();
+        }
+    }
+    Ok(size)
+}
+
 pub struct ReadOnly;
 pub struct ReadWrite;
 
 pub struct File<R> {
     _phantom: PhantomData<R>,
-    path: PathBuf,
+    path: Result<PathBuf, InvalidNameError>,
 }
 
 impl<R> File<R> {
-    fn new(path: PathBuf) -> Self {
+    fn new(path: Result<PathBuf, InvalidNameError>) -> Self {
         Self {
             _phantom: PhantomData,
             path,
         }
     }
+
+    fn path(&self) -> std::io::Result<&PathBuf> {
+        self.path.as_ref().map_err(|e| e.clone().into())
+    }
 }
 
 impl<R> super::super::File for File<R> {
@@ -44,11 +108,23 @@ impl<R> super::super::File for File<R> {
     type ReadBinary = std::fs::File;
 
     fn exists(&self) -> std::io::Result<bool> {
-        Ok(self.path.exists())
+        Ok(self.path()?.exists())
+    }
+
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        let metadata = std::fs::metadata(self.path()?)?;
+        Ok(Metadata {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        watch_file(self.path()?.clone())
     }
 
     fn read_text(&self) -> std::io::Result<Self::ReadText> {
-        std::fs::File::open(&self.path)
+        std::fs::File::open(self.path()?)
     }
 
     fn read_binary(&self) -> std::io::Result<Self::ReadBinary> {
@@ -61,59 +137,159 @@ impl super::super::WritableFile for File<ReadWrite> {
     type WriteBinary = FileWrite;
 
     fn remove(&mut self) -> std::io::Result<()> {
-        remove_file(&self.path)
+        match remove_file(self.path()?) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
     }
 
     fn write_text(&mut self) -> std::io::Result<Self::WriteText> {
-        if let Some(dir_path) = self.path.parent() {
+        let path = self.path()?;
+        if let Some(dir_path) = path.parent() {
             create_dir_all(dir_path)?;
         }
-        Ok(FileWrite::new(AtomicWriteFile::open(&self.path)?))
+        Ok(FileWrite::new(AtomicWriteFile::open(path)?))
     }
 
     fn write_binary(&mut self) -> std::io::Result<Self::WriteText> {
         self.write_text()
     }
+
+    fn location(&self) -> std::io::Result<String> {
+        Ok(self.path()?.display().to_string())
+    }
+
+    fn copy_to(&self, target: &mut Self) -> std::io::Result<()> {
+        let (path, target_path) = (self.path()?, target.path()?);
+        if path == target_path {
+            return Ok(());
+        }
+        let mut read = std::fs::File::open(path)?;
+        if let Some(dir_path) = target_path.parent() {
+            create_dir_all(dir_path)?;
+        }
+        let mut write = AtomicWriteFile::open(target_path)?;
+        std::io::copy(&mut read, &mut write)?;
+        write.commit()
+    }
+
+    fn move_to(&mut self, target: &mut Self) -> std::io::Result<()> {
+        let (path, target_path) = (self.path()?, target.path()?);
+        if path == target_path {
+            return Ok(());
+        }
+        if let Some(dir_path) = target_path.parent() {
+            create_dir_all(dir_path)?;
+        }
+        rename(path, target_path)
+    }
+
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        lock::try_lock(self.path()?)
+    }
 }
 
 pub struct Dir<R> {
     _phantom: PhantomData<R>,
-    path: PathBuf,
+    path: Result<PathBuf, InvalidNameError>,
 }
 
 impl<R> Dir<R> {
-    fn new(path: PathBuf) -> Self {
+    fn new(path: Result<PathBuf, InvalidNameError>) -> Self {
         Self {
             _phantom: PhantomData,
             path,
         }
     }
 
-    fn new_outer(
-        path: PathBuf,
-        path_prefix: Option<&PathBuf>,
-    ) -> Result<Self, OuterDirectoryError> {
-        let mut new = Self::new(path);
-        if let Some(path_prefix) = path_prefix {
-            // This is so we can re-root paths for testing.
-            let mut components: Vec<_> = path_prefix.components().collect();
-            components.extend(new.path.components().filter(|c| c != &Component::RootDir));
-            new.path = components.iter().collect();
-        }
-        create_dir_all(&new.path).map_err(|_| OuterDirectoryError::NotAvailable)?;
-        if new.path.is_dir() {
-            Ok(new)
+    fn new_outer(path: &Path) -> Result<Self, StorageError> {
+        let path = path.to_path_buf();
+        create_dir_all(&path).map_err(StorageError::backend)?;
+        if path.is_dir() {
+            Ok(Self::new(Ok(path)))
         } else {
-            Err(OuterDirectoryError::NotAvailable)
+            Err(StorageError::backend("not a directory"))
         }
     }
+
+    fn path(&self) -> std::io::Result<&PathBuf> {
+        self.path.as_ref().map_err(|e| e.clone().into())
+    }
 }
 
 impl<R> super::super::Dir for Dir<R> {
     type File = File<R>;
 
     fn file(&self, name: Cow<'static, str>) -> Self::File {
-        File::new(self.path.join(name.to_string()))
+        File::new(join(&self.path, &name))
+    }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        let dir_entries = match read_dir(self.path()?) {
+            Ok(dir_entries) => dir_entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut entries = Vec::new();
+        for dir_entry in dir_entries {
+            let dir_entry = dir_entry?;
+            // Names we could not have created ourselves, including lock files, are skipped.
+            let Ok(name) = dir_entry.file_name().into_string() else {
+                continue;
+            };
+            if validate_name(&name).is_err() {
+                continue;
+            }
+            let file_type = dir_entry.file_type()?;
+            // Directories left behind holding only lock files count as removed.
+            if file_type.is_dir() {
+                if has_files(&dir_entry.path())? {
+                    entries.push(Entry::dir(name));
+                }
+            } else if file_type.is_file() {
+                entries.push(Entry::file(name));
+            }
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        watch_dir(self.path()?.clone())
+    }
+}
+
+/// Remove everything under a directory except lock files, which could be in use by another
+/// process. Subdirectories are removed unless they still hold lock files.
+fn remove_contents(path: &Path) -> std::io::Result<()> {
+    let dir_entries = match read_dir(path) {
+        Ok(dir_entries) => dir_entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for dir_entry in dir_entries {
+        let dir_entry = dir_entry?;
+        if dir_entry.file_type()?.is_dir() {
+            remove_contents(&dir_entry.path())?;
+            remove_empty_dir(&dir_entry.path())?;
+        } else if !is_lock_file(&dir_entry.file_name().to_string_lossy()) {
+            remove_file(dir_entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_empty_dir(path: &Path) -> std::io::Result<()> {
+    match remove_dir(path) {
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::DirectoryNotEmpty
+            ) =>
+        {
+            Ok(())
+        }
+        result => result,
     }
 }
 
@@ -121,7 +297,11 @@ impl super::super::WritableDir for Dir<ReadWrite> {
     type WritableFile = File<ReadWrite>;
 
     fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
-        File::new(self.path.join(name.to_string()))
+        File::new(join(&self.path, &name))
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        remove_contents(self.path()?)
     }
 }
 
@@ -129,7 +309,7 @@ impl<R> super::super::ParentDir for Dir<R> {
     type LeafDir = Dir<R>;
 
     fn subdir(&self, name: Cow<'static, str>) -> Self {
-        Self::new(self.path.join(name.to_string()))
+        Self::new(join(&self.path, &name))
     }
 
     fn into_leaf(self) -> Self::LeafDir {
@@ -141,81 +321,179 @@ impl super::super::WritableParentDir for Dir<ReadWrite> {
     type WritableLeafDir = Dir<ReadWrite>;
 
     fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self {
-        Self {
-            _phantom: PhantomData,
-            path: self.path.join(name.to_string()),
-        }
+        Self::new(join(&self.path, &name))
     }
 
     fn into_writable_leaf(self) -> Self::WritableLeafDir {
         self
     }
+
+    fn remove_subdir(&mut self, name: Cow<'static, str>) -> std::io::Result<()> {
+        let path = join(&self.path, &name)?;
+        remove_contents(&path)?;
+        remove_empty_dir(&path)
+    }
 }
 
 pub struct Storage {
-    path_prefix: Option<PathBuf>,
-    project_dirs: ProjectDirs,
+    paths: BTreeMap<Category, PathBuf>,
+    recover: RecoverOnOpen,
+}
+
+/// Where the platform keeps things for the application, where it has a place for them.
+struct PlatformDirs {
+    data: PathBuf,
+    config: PathBuf,
+    cache: PathBuf,
+    /// Data which should not roam between machines, which is the same as `data` except on
+    /// Windows.
+    local: PathBuf,
+    state: Option<PathBuf>,
+    runtime: Option<PathBuf>,
+    saves: Option<PathBuf>,
+    screenshots: Option<PathBuf>,
+}
+
+/// A directory next to `dir`, named after it and the category.
+fn beside(dir: &Path, category: Category) -> PathBuf {
+    let mut name = dir.file_name().unwrap_or_default().to_os_string();
+    name.push("-");
+    name.push(category.name());
+    dir.with_file_name(name)
 }
 
 impl Storage {
+    /// Storage in the standard locations for the platform. State, runtime and log directories
+    /// use XDG locations where there are any. Saves and screenshots go in a directory named after
+    /// the application in the user's documents and pictures directories. Categories which the
+    /// platform has no place for go next to the local data. No category is inside another, so
+    /// clearing one leaves the others alone.
     pub fn new(
         qualifier: &str,
         organization: &str,
         application: &str,
     ) -> Result<Self, StandardStorageAvailabilityError> {
-        ProjectDirs::from(qualifier, organization, application)
-            .map(|pd| Self {
-                path_prefix: None,
-                project_dirs: pd,
+        let pd = ProjectDirs::from(qualifier, organization, application)
+            .ok_or(StandardStorageAvailabilityError::UnknownHomeDirectory)?;
+        let user_dirs = UserDirs::new();
+        let user_dir = |dir: fn(&UserDirs) -> Option<&Path>| {
+            user_dirs
+                .as_ref()
+                .and_then(dir)
+                .map(|dir| dir.join(application))
+        };
+        Ok(Self::with_platform_dirs(PlatformDirs {
+            data: pd.data_dir().into(),
+            // The same as the config directory, except on macOS where that is the data directory.
+            config: pd.preference_dir().into(),
+            cache: pd.cache_dir().into(),
+            local: pd.data_local_dir().into(),
+            state: pd.state_dir().map(Path::to_path_buf),
+            runtime: pd.runtime_dir().map(Path::to_path_buf),
+            saves: user_dir(UserDirs::document_dir),
+            screenshots: user_dir(UserDirs::picture_dir),
+        }))
+    }
+
+    fn with_platform_dirs(dirs: PlatformDirs) -> Self {
+        let fallback = |category: Category| {
+            if cfg!(windows) {
+                // Local data is already in a `data` directory of its own for the application.
+                dirs.local.with_file_name(category.name())
+            } else {
+                beside(&dirs.local, category)
+            }
+        };
+        let paths = Category::ALL
+            .into_iter()
+            .map(|category| {
+                let path = match category {
+                    Category::Data => Some(dirs.data.clone()),
+                    Category::Config => Some(dirs.config.clone()),
+                    Category::Cache => Some(dirs.cache.clone()),
+                    Category::State => dirs.state.clone(),
+                    Category::Runtime => dirs.runtime.clone(),
+                    Category::Logs => dirs.state.as_ref().map(|state| beside(state, category)),
+                    Category::Saves => dirs.saves.clone(),
+                    Category::Screenshots => dirs.screenshots.clone(),
+                };
+                (category, path.unwrap_or_else(|| fallback(category)))
             })
-            .ok_or(StandardStorageAvailabilityError::UnknownHomeDirectory)
+            .collect();
+        Self {
+            paths,
+            recover: RecoverOnOpen::default(),
+        }
     }
-}
 
-impl super::super::Storage for Storage {
-    type Dir = Dir<ReadOnly>;
-    type WritableDir = Dir<ReadWrite>;
+    /// Storage with a directory for each category under `root`, named by [Category::name].
+    pub fn with_root(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        Self {
+            paths: Category::ALL
+                .into_iter()
+                .map(|category| (category, root.join(category.name())))
+                .collect(),
+            recover: RecoverOnOpen::default(),
+        }
+    }
 
-    fn data(&self) -> Result<Self::Dir, OuterDirectoryError> {
-        Dir::new_outer(
-            self.project_dirs.data_dir().into(),
-            self.path_prefix.as_ref(),
-        )
+    /// Storage under the `storage` directory next to the executable.
+    pub fn portable() -> Result<Self, StandardStorageAvailabilityError> {
+        let exe = std::env::current_exe()
+            .map_err(|_| StandardStorageAvailabilityError::UnknownExecutableDirectory)?;
+        let dir = exe
+            .parent()
+            .ok_or(StandardStorageAvailabilityError::UnknownExecutableDirectory)?;
+        Ok(Self::with_root(dir.join("storage")))
     }
 
-    fn config(&self) -> Result<Self::Dir, OuterDirectoryError> {
-        Dir::new_outer(
-            self.project_dirs.config_dir().into(),
-            self.path_prefix.as_ref(),
-        )
+    pub(crate) fn category_path(&self, category: Category) -> Result<&Path, StorageError> {
+        self.paths
+            .get(&category)
+            .map(PathBuf::as_path)
+            .ok_or(StorageError::Unsupported)
     }
 
-    fn cache(&self) -> Result<Self::Dir, OuterDirectoryError> {
-        Dir::new_outer(
-            self.project_dirs.cache_dir().into(),
-            self.path_prefix.as_ref(),
-        )
+    fn outer_dir<R>(&self, category: Category) -> Result<Dir<R>, StorageError> {
+        let path = self.category_path(category)?;
+        self.recover
+            .open(category, || Dir::<ReadWrite>::new_outer(path))?;
+        Dir::new_outer(path)
+    }
+}
+
+impl super::super::Storage for Storage {
+    type Dir = Dir<ReadOnly>;
+    type WritableDir = Dir<ReadWrite>;
+
+    fn dir(&self, category: Category) -> Result<Self::Dir, StorageError> {
+        self.outer_dir(category)
     }
 
-    fn writable_data(&mut self) -> Result<Self::WritableDir, OuterDirectoryError> {
-        Dir::new_outer(
-            self.project_dirs.data_dir().into(),
-            self.path_prefix.as_ref(),
-        )
+    fn writable_dir(&mut self, category: Category) -> Result<Self::WritableDir, StorageError> {
+        self.outer_dir(category)
     }
 
-    fn writable_config(&mut self) -> Result<Self::WritableDir, OuterDirectoryError> {
-        Dir::new_outer(
-            self.project_dirs.config_dir().into(),
-            self.path_prefix.as_ref(),
-        )
+    async fn space(&self) -> std::io::Result<Space> {
+        // Directories chosen through XDG variables can still be the same or inside each other.
+        let mut paths: Vec<_> = self.paths.values().collect();
+        paths.sort();
+        paths.dedup_by(|path, outer| path.starts_with(outer));
+        let mut used = 0;
+        for path in paths {
+            used += size_under(path)?;
+        }
+        let data = self.data()?;
+        Ok(Space {
+            used: Some(used),
+            available: Some(fs4::available_space(data.path()?)?),
+        })
     }
 
-    fn writable_cache(&mut self) -> Result<Self::WritableDir, OuterDirectoryError> {
-        Dir::new_outer(
-            self.project_dirs.cache_dir().into(),
-            self.path_prefix.as_ref(),
-        )
+    async fn request_persistence(&mut self) -> std::io::Result<bool> {
+        // Files are never removed behind our back.
+        Ok(true)
     }
 }
 
@@ -227,27 +505,74 @@ mod tests {
     use tempfile::tempdir;
 
     fn make_storage() -> Storage {
-        let temp = tempdir().unwrap();
-        let mut storage = Storage::new("a", "b", "c").unwrap();
-        storage.path_prefix = Some(temp.path().into());
-        storage
+        Storage::with_root(tempdir().unwrap().path())
     }
 
     #[test]
     fn storage_uniqueness() {
         let storage0 = Storage::new("a", "b", "c").unwrap();
         let storage1 = Storage::new("d", "e", "f").unwrap();
-        assert_ne!(storage0.data().unwrap().path, storage1.data().unwrap().path);
         assert_ne!(
-            storage0.config().unwrap().path,
-            storage1.config().unwrap().path
+            storage0.data().unwrap().path().unwrap(),
+            storage1.data().unwrap().path().unwrap()
+        );
+        assert_ne!(
+            storage0.config().unwrap().path().unwrap(),
+            storage1.config().unwrap().path().unwrap()
         );
         assert_ne!(
-            storage0.cache().unwrap().path,
-            storage1.cache().unwrap().path
+            storage0.cache().unwrap().path().unwrap(),
+            storage1.cache().unwrap().path().unwrap()
         );
     }
 
+    #[test]
+    fn separate_categories() {
+        use super::super::super::{Dir as _, Entry, WritableDir as _};
+        let temp = tempdir().unwrap();
+        let dir = |path: &str| temp.path().join(path);
+        let linux = PlatformDirs {
+            data: dir("share/app"),
+            config: dir("config/app"),
+            cache: dir("cache/app"),
+            local: dir("share/app"),
+            state: Some(dir("state/app")),
+            runtime: Some(dir("run/app")),
+            saves: Some(dir("Documents/app")),
+            screenshots: Some(dir("Pictures/app")),
+        };
+        let mac = PlatformDirs {
+            data: dir("Application Support/com.app"),
+            config: dir("Preferences/com.app"),
+            cache: dir("Caches/com.app"),
+            local: dir("Application Support/com.app"),
+            state: None,
+            runtime: None,
+            saves: None,
+            screenshots: None,
+        };
+        for dirs in [linux, mac] {
+            let mut storage = Storage::with_platform_dirs(dirs);
+            for category in Category::ALL {
+                let mut dir = storage.writable_dir(category).unwrap();
+                generic_tests::write_text(&mut dir.writable_file("a".into()), b"a");
+            }
+            for cleared in Category::ALL {
+                storage.writable_dir(cleared).unwrap().clear().unwrap();
+                for category in Category::ALL {
+                    let expected = if category == cleared {
+                        vec![]
+                    } else {
+                        vec![Entry::file("a")]
+                    };
+                    assert_eq!(storage.dir(category).unwrap().entries().unwrap(), expected);
+                }
+                let mut dir = storage.writable_dir(cleared).unwrap();
+                generic_tests::write_text(&mut dir.writable_file("a".into()), b"a");
+            }
+        }
+    }
+
     #[test]
     fn text_file() {
         generic_tests::text_file(make_storage());
@@ -262,4 +587,85 @@ mod tests {
     fn file_uniqueness() {
         generic_tests::file_uniqueness(make_storage());
     }
+
+    #[test]
+    fn categories() {
+        generic_tests::categories(make_storage());
+    }
+
+    #[test]
+    fn locking() {
+        generic_tests::locking(make_storage());
+    }
+
+    #[test]
+    fn locks_kept_when_removing() {
+        use super::super::super::{
+            Dir as _, File as _, WritableDir as _, WritableFile as _, WritableParentDir as _,
+        };
+        let mut storage = make_storage();
+        let mut dir = storage.writable_data().unwrap();
+        let mut sub = dir.writable_subdir("sub".into());
+        let _lock = sub.writable_file("save".into()).try_lock().unwrap();
+        generic_tests::write_text(&mut sub.writable_file("save".into()), b"save");
+        let lock_path = sub.path().unwrap().join(".save.dias-lock");
+        dir.remove_subdir("sub".into()).unwrap();
+        assert_eq!(dir.entries().unwrap(), vec![]);
+        dir.clear().unwrap();
+        assert!(lock_path.is_file());
+        assert!(!sub.file("save".into()).exists().unwrap());
+        assert_eq!(
+            sub.writable_file("save".into())
+                .try_lock()
+                .err()
+                .unwrap()
+                .kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    fn metadata() {
+        generic_tests::metadata(make_storage());
+    }
+
+    #[test]
+    fn missing_file() {
+        generic_tests::missing_file(make_storage());
+    }
+
+    #[test]
+    fn commit_and_abort() {
+        generic_tests::commit_and_abort(make_storage());
+    }
+
+    #[test]
+    fn copy_and_move() {
+        generic_tests::copy_and_move(make_storage());
+    }
+
+    #[test]
+    fn removal() {
+        generic_tests::removal(make_storage());
+    }
+
+    #[test]
+    fn names() {
+        generic_tests::names(make_storage());
+    }
+
+    #[test]
+    fn listing() {
+        generic_tests::listing(make_storage());
+    }
+
+    #[test]
+    fn space() {
+        pollster::block_on(generic_tests::space(make_storage()));
+    }
+
+    #[test]
+    fn watching() {
+        generic_tests::watching(make_storage());
+    }
 }
@@ -0,0 +1,100 @@
+//! Watching by comparing snapshots of file metadata, since there is no portable way to be told
+//! about changes.
+
+use super::super::name::validate_name;
+use super::super::{Change, ChangeKind, Watcher};
+use std::collections::BTreeMap;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+type Snapshot = BTreeMap<String, (u64, Option<SystemTime>)>;
+
+fn snapshot_dir(path: &Path, prefix: &str, snapshot: &mut Snapshot) -> std::io::Result<()> {
+    let dir_entries = match read_dir(path) {
+        Ok(dir_entries) => dir_entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for dir_entry in dir_entries {
+        let dir_entry = dir_entry?;
+        // Names we could not have created ourselves, including lock files, are skipped, as when
+        // listing.
+        let Ok(name) = dir_entry.file_name().into_string() else {
+            continue;
+        };
+        if validate_name(&name).is_err() {
+            continue;
+        }
+        let file_type = dir_entry.file_type()?;
+        if file_type.is_dir() {
+            snapshot_dir(&dir_entry.path(), &format!("{}{}/", prefix, name), snapshot)?;
+        } else if file_type.is_file() {
+            let metadata = dir_entry.metadata()?;
+            snapshot.insert(
+                format!("{}{}", prefix, name),
+                (metadata.len(), metadata.modified().ok()),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn snapshot_file(path: &Path, name: &str) -> std::io::Result<Snapshot> {
+    let mut snapshot = Snapshot::new();
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => {
+            snapshot.insert(name.to_owned(), (metadata.len(), metadata.modified().ok()));
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok(snapshot)
+}
+
+fn diff(old: &Snapshot, new: &Snapshot) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for (name, metadata) in new {
+        match old.get(name) {
+            None => changes.push(Change::new(name, ChangeKind::Created)),
+            Some(old_metadata) if old_metadata != metadata => {
+                changes.push(Change::new(name, ChangeKind::Modified))
+            }
+            Some(_) => {}
+        }
+    }
+    for name in old.keys().filter(|name| !new.contains_key(*name)) {
+        changes.push(Change::new(name, ChangeKind::Removed));
+    }
+    changes
+}
+
+fn polling(
+    mut take_snapshot: impl FnMut() -> std::io::Result<Snapshot> + 'static,
+) -> std::io::Result<Watcher> {
+    let mut snapshot = take_snapshot()?;
+    Ok(Watcher::new(move || {
+        let new_snapshot = take_snapshot()?;
+        let changes = diff(&snapshot, &new_snapshot);
+        snapshot = new_snapshot;
+        Ok(changes)
+    }))
+}
+
+pub fn watch_dir(path: PathBuf) -> std::io::Result<Watcher> {
+    polling(move || {
+        let mut snapshot = Snapshot::new();
+        snapshot_dir(&path, "", &mut snapshot)?;
+        Ok(snapshot)
+    })
+}
+
+pub fn watch_file(path: PathBuf) -> std::io::Result<Watcher> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+        .to_owned();
+    polling(move || snapshot_file(&path, &name))
+}
@@ -1,6 +1,8 @@
+use crate::storage::generic::{finished_writer, Commit};
 use atomic_write_file::AtomicWriteFile;
 use std::io::{IoSlice, Write};
 
+/// Writes to a temporary file which replaces the file when committed.
 pub struct FileWrite {
     source: Option<AtomicWriteFile>,
 }
@@ -12,41 +14,47 @@ impl FileWrite {
         }
     }
 
-    fn source(&self) -> &AtomicWriteFile {
-        self.source
-            .as_ref()
-            .expect("should have underlying file until dropped")
+    fn source(&self) -> std::io::Result<&AtomicWriteFile> {
+        self.source.as_ref().ok_or_else(finished_writer)
     }
 }
 
 impl Write for FileWrite {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.source().write(buf)
+        self.source()?.write(buf)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        self.source().flush()
+        self.source()?.flush()
     }
 
     fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
-        self.source().write_vectored(bufs)
+        self.source()?.write_vectored(bufs)
     }
 
     fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
-        self.source().write_all(buf)
+        self.source()?.write_all(buf)
     }
 
     fn write_fmt(&mut self, fmt: std::fmt::Arguments<'_>) -> std::io::Result<()> {
-        self.source().write_fmt(fmt)
+        self.source()?.write_fmt(fmt)
     }
 }
 
-impl Drop for FileWrite {
-    fn drop(&mut self) {
-        // AtomicWriteFile doesn't seem to commit on drop as expected (at least in test code), so
-        // we call it explicitly in this wrapper.
+impl Commit for FileWrite {
+    fn commit(&mut self) -> std::io::Result<()> {
+        self.source.take().ok_or_else(finished_writer)?.commit()
+    }
+
+    fn abort(&mut self) {
         if let Some(source) = self.source.take() {
-            let _ = source.commit();
+            let _ = source.discard();
         }
     }
 }
+
+impl Drop for FileWrite {
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
@@ -0,0 +1,9 @@
+use std::time::SystemTime;
+
+/// Get the current time. [SystemTime::now] panics on web, so there we ask the browser instead.
+pub fn now() -> SystemTime {
+    #[cfg(not(target_arch = "wasm32"))]
+    return SystemTime::now();
+    #[cfg(target_arch = "wasm32")]
+    return SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(js_sys::Date::now() as u64);
+}
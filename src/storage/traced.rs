@@ -0,0 +1,772 @@
+//! Storage which records every operation, wrapping any other storage, for attaching traces to bug
+//! reports and checking exact sequences of operations in tests.
+//!
+//! Records go into a [Trace] shared by everything made from the storage, and are kept until taken.
+//! Only the latest [DEFAULT_CAPACITY] records are kept, or as many as set by
+//! [TracedStorage::capacity], so that long running tracing does not use up memory. With the `log`
+//! feature, each record is also logged at debug level as it is made.
+//!
+//! Reads and writes are recorded with the number of bytes moved. A read is recorded when the
+//! reader is dropped, as failed if any read failed, and a write when the writer is flushed,
+//! committed or dropped, rather than for every call. A writer dropped without committing is recorded as aborted. Paths start with the
+//! category name and are separated by `/`.
+//!
+//! ```rust
+//! use std::io::Write;
+//! use dias::storage::traced::{Operation, Outcome, Record, TracedStorage};
+//! use dias::storage::{Commit, File, MemoryStorage, Storage, WritableDir, WritableFile};
+//!
+//! let mut storage = TracedStorage::new(MemoryStorage::new());
+//! let mut file = storage.writable_config().unwrap().writable_file("settings".into());
+//! let mut write = file.write_text().unwrap();
+//! write.write_all(b"volume = 5").unwrap();
+//! write.commit().unwrap();
+//! assert_eq!(storage.trace().take().len(), 5);
+//! file.exists().unwrap();
+//! assert_eq!(
+//!     storage.trace().take(),
+//!     vec![Record::new(Operation::Exists, "config/settings", Outcome::Exists(true))]
+//! );
+//! ```
+
+use crate::storage::error::StorageError;
+use crate::storage::generic::{
+    Category, Commit, Dir, Entry, File, FileLock, Metadata, ParentDir, Space, Storage, Watcher,
+    WritableDir, WritableFile, WritableParentDir,
+};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// Number of records a [Trace] keeps unless set by [TracedStorage::capacity].
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Dir,
+    WritableDir,
+    File,
+    WritableFile,
+    Subdir,
+    WritableSubdir,
+    Exists,
+    Metadata,
+    Watch,
+    /// Opening a file for reading.
+    OpenRead,
+    Read,
+    /// Opening a file for writing.
+    OpenWrite,
+    Write,
+    Flush,
+    Commit,
+    /// Aborting a writer, or dropping it without committing.
+    Abort,
+    Remove,
+    /// Copying to the given path.
+    CopyTo(String),
+    /// Moving to the given path.
+    MoveTo(String),
+    Lock,
+    Entries,
+    Clear,
+    RemoveSubdir,
+    Space,
+    RequestPersistence,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    /// Whether the file exists.
+    Exists(bool),
+    /// Bytes read or written.
+    Bytes(u64),
+    /// Number of entries listed.
+    Entries(usize),
+    Failed(std::io::ErrorKind),
+}
+
+impl<T> From<&std::io::Result<T>> for Outcome {
+    fn from(result: &std::io::Result<T>) -> Self {
+        match result {
+            Ok(_) => Outcome::Ok,
+            Err(e) => Outcome::Failed(e.kind()),
+        }
+    }
+}
+
+/// One operation on storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub operation: Operation,
+    pub path: String,
+    pub outcome: Outcome,
+}
+
+impl Record {
+    pub fn new(operation: Operation, path: &str, outcome: Outcome) -> Self {
+        Self {
+            operation,
+            path: path.to_string(),
+            outcome,
+        }
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {}: {:?}", self.operation, self.path, self.outcome)
+    }
+}
+
+struct Records {
+    records: VecDeque<Record>,
+    capacity: usize,
+}
+
+impl Records {
+    fn truncate(&mut self) {
+        while self.records.len() > self.capacity {
+            self.records.pop_front();
+        }
+    }
+}
+
+/// Records of operations, shared by a [TracedStorage] and everything made from it. Once full, the
+/// oldest records are dropped to make room for new ones.
+#[derive(Clone)]
+pub struct Trace {
+    records: Arc<Mutex<Records>>,
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self {
+            records: Arc::new(Mutex::new(Records {
+                records: VecDeque::new(),
+                capacity: DEFAULT_CAPACITY,
+            })),
+        }
+    }
+}
+
+impl Trace {
+    fn push(&self, operation: Operation, path: &str, outcome: Outcome) {
+        let record = Record::new(operation, path, outcome);
+        #[cfg(feature = "log")]
+        log::debug!("{}", record);
+        if let Ok(mut records) = self.records.lock() {
+            records.records.push_back(record);
+            records.truncate();
+        }
+    }
+
+    fn set_capacity(&self, capacity: usize) {
+        if let Ok(mut records) = self.records.lock() {
+            records.capacity = capacity;
+            records.truncate();
+        }
+    }
+
+    fn push_result<T>(
+        &self,
+        operation: Operation,
+        path: &str,
+        result: std::io::Result<T>,
+    ) -> std::io::Result<T> {
+        self.push(operation, path, (&result).into());
+        result
+    }
+
+    /// Copy the records so far.
+    pub fn records(&self) -> Vec<Record> {
+        self.records
+            .lock()
+            .map(|records| records.records.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Take the records so far, leaving none.
+    pub fn take(&self) -> Vec<Record> {
+        self.records
+            .lock()
+            .map(|mut records| std::mem::take(&mut records.records).into())
+            .unwrap_or_default()
+    }
+}
+
+fn join(parent: &str, name: &str) -> String {
+    format!("{}/{}", parent, name)
+}
+
+pub struct TracedRead<R> {
+    read: R,
+    trace: Trace,
+    path: String,
+    bytes: u64,
+    failed: Option<std::io::ErrorKind>,
+}
+
+impl<R: Read> Read for TracedRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.read.read(buf) {
+            Ok(len) => {
+                self.bytes += len as u64;
+                Ok(len)
+            }
+            Err(e) => {
+                self.failed = Some(e.kind());
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<R> Drop for TracedRead<R> {
+    fn drop(&mut self) {
+        let outcome = match self.failed {
+            Some(kind) => Outcome::Failed(kind),
+            None => Outcome::Bytes(self.bytes),
+        };
+        self.trace.push(Operation::Read, &self.path, outcome);
+    }
+}
+
+pub struct TracedWrite<W> {
+    write: W,
+    trace: Trace,
+    path: String,
+    bytes: u64,
+    finished: bool,
+}
+
+impl<W> TracedWrite<W> {
+    fn record_bytes(&mut self) {
+        if self.bytes > 0 {
+            self.trace
+                .push(Operation::Write, &self.path, Outcome::Bytes(self.bytes));
+            self.bytes = 0;
+        }
+    }
+}
+
+impl<W: Write> Write for TracedWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.write.write(buf) {
+            Ok(len) => {
+                self.bytes += len as u64;
+                Ok(len)
+            }
+            Err(e) => {
+                self.record_bytes();
+                self.trace
+                    .push(Operation::Write, &self.path, Outcome::Failed(e.kind()));
+                Err(e)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.record_bytes();
+        let result = self.write.flush();
+        self.trace.push_result(Operation::Flush, &self.path, result)
+    }
+}
+
+impl<W: Commit> Commit for TracedWrite<W> {
+    fn commit(&mut self) -> std::io::Result<()> {
+        self.record_bytes();
+        let result = self.write.commit();
+        self.finished |= result.is_ok();
+        self.trace
+            .push_result(Operation::Commit, &self.path, result)
+    }
+
+    fn abort(&mut self) {
+        self.record_bytes();
+        if !self.finished {
+            self.trace.push(Operation::Abort, &self.path, Outcome::Ok);
+            self.finished = true;
+        }
+        self.write.abort()
+    }
+}
+
+impl<W> Drop for TracedWrite<W> {
+    fn drop(&mut self) {
+        self.record_bytes();
+        if !self.finished {
+            self.trace.push(Operation::Abort, &self.path, Outcome::Ok);
+        }
+    }
+}
+
+pub struct TracedFile<F> {
+    source: F,
+    trace: Trace,
+    path: String,
+}
+
+impl<F: File> File for TracedFile<F> {
+    type ReadText = TracedRead<F::ReadText>;
+    type ReadBinary = TracedRead<F::ReadBinary>;
+
+    fn exists(&self) -> std::io::Result<bool> {
+        let result = self.source.exists();
+        let outcome = match &result {
+            Ok(exists) => Outcome::Exists(*exists),
+            Err(e) => Outcome::Failed(e.kind()),
+        };
+        self.trace.push(Operation::Exists, &self.path, outcome);
+        result
+    }
+
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        let result = self.source.metadata();
+        self.trace
+            .push_result(Operation::Metadata, &self.path, result)
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        let result = self.source.watch();
+        self.trace.push_result(Operation::Watch, &self.path, result)
+    }
+
+    fn read_text(&self) -> std::io::Result<Self::ReadText> {
+        let result = self.source.read_text();
+        let read = self
+            .trace
+            .push_result(Operation::OpenRead, &self.path, result)?;
+        Ok(self.read(read))
+    }
+
+    fn read_binary(&self) -> std::io::Result<Self::ReadBinary> {
+        let result = self.source.read_binary();
+        let read = self
+            .trace
+            .push_result(Operation::OpenRead, &self.path, result)?;
+        Ok(self.read(read))
+    }
+}
+
+impl<F> TracedFile<F> {
+    fn read<R>(&self, read: R) -> TracedRead<R> {
+        TracedRead {
+            read,
+            trace: self.trace.clone(),
+            path: self.path.clone(),
+            bytes: 0,
+            failed: None,
+        }
+    }
+
+    fn write<W>(&self, write: W) -> TracedWrite<W> {
+        TracedWrite {
+            write,
+            trace: self.trace.clone(),
+            path: self.path.clone(),
+            bytes: 0,
+            finished: false,
+        }
+    }
+}
+
+impl<F: WritableFile> WritableFile for TracedFile<F> {
+    type WriteText = TracedWrite<F::WriteText>;
+    type WriteBinary = TracedWrite<F::WriteBinary>;
+
+    fn remove(&mut self) -> std::io::Result<()> {
+        let result = self.source.remove();
+        self.trace
+            .push_result(Operation::Remove, &self.path, result)
+    }
+
+    fn write_text(&mut self) -> std::io::Result<Self::WriteText> {
+        let result = self.source.write_text();
+        let write = self
+            .trace
+            .push_result(Operation::OpenWrite, &self.path, result)?;
+        Ok(self.write(write))
+    }
+
+    fn write_binary(&mut self) -> std::io::Result<Self::WriteBinary> {
+        let result = self.source.write_binary();
+        let write = self
+            .trace
+            .push_result(Operation::OpenWrite, &self.path, result)?;
+        Ok(self.write(write))
+    }
+
+    fn location(&self) -> std::io::Result<String> {
+        self.source.location()
+    }
+
+    fn copy_to(&self, target: &mut Self) -> std::io::Result<()> {
+        let result = self.source.copy_to(&mut target.source);
+        let operation = Operation::CopyTo(target.path.clone());
+        self.trace.push_result(operation, &self.path, result)
+    }
+
+    fn move_to(&mut self, target: &mut Self) -> std::io::Result<()> {
+        let result = self.source.move_to(&mut target.source);
+        let operation = Operation::MoveTo(target.path.clone());
+        self.trace.push_result(operation, &self.path, result)
+    }
+
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        let result = self.source.try_lock();
+        self.trace.push_result(Operation::Lock, &self.path, result)
+    }
+}
+
+pub struct TracedDir<D> {
+    source: D,
+    trace: Trace,
+    path: String,
+}
+
+impl<D> TracedDir<D> {
+    /// Trace a directory on its own, recording paths starting with the given one.
+    pub fn new(source: D, trace: Trace, path: &str) -> Self {
+        Self {
+            source,
+            trace,
+            path: path.to_string(),
+        }
+    }
+
+    fn wrap<T>(&self, operation: Operation, name: &str, source: T) -> TracedDir<T> {
+        let path = join(&self.path, name);
+        self.trace.push(operation, &path, Outcome::Ok);
+        TracedDir {
+            source,
+            trace: self.trace.clone(),
+            path,
+        }
+    }
+
+    fn wrap_file<F>(&self, operation: Operation, name: &str, source: F) -> TracedFile<F> {
+        let path = join(&self.path, name);
+        self.trace.push(operation, &path, Outcome::Ok);
+        TracedFile {
+            source,
+            trace: self.trace.clone(),
+            path,
+        }
+    }
+}
+
+impl<D: Dir> Dir for TracedDir<D> {
+    type File = TracedFile<D::File>;
+
+    fn file(&self, name: Cow<'static, str>) -> Self::File {
+        self.wrap_file(Operation::File, &name.clone(), self.source.file(name))
+    }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        let result = self.source.entries();
+        let outcome = match &result {
+            Ok(entries) => Outcome::Entries(entries.len()),
+            Err(e) => Outcome::Failed(e.kind()),
+        };
+        self.trace.push(Operation::Entries, &self.path, outcome);
+        result
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        let result = self.source.watch();
+        self.trace.push_result(Operation::Watch, &self.path, result)
+    }
+}
+
+impl<D: WritableDir> WritableDir for TracedDir<D> {
+    type WritableFile = TracedFile<D::WritableFile>;
+
+    fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
+        let source = self.source.writable_file(name.clone());
+        self.wrap_file(Operation::WritableFile, &name, source)
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        let result = self.source.clear();
+        self.trace.push_result(Operation::Clear, &self.path, result)
+    }
+}
+
+impl<D: ParentDir> ParentDir for TracedDir<D> {
+    type LeafDir = TracedDir<D::LeafDir>;
+
+    fn subdir(&self, name: Cow<'static, str>) -> Self {
+        self.wrap(Operation::Subdir, &name.clone(), self.source.subdir(name))
+    }
+
+    fn into_leaf(self) -> Self::LeafDir {
+        TracedDir {
+            source: self.source.into_leaf(),
+            trace: self.trace,
+            path: self.path,
+        }
+    }
+}
+
+impl<D: WritableParentDir> WritableParentDir for TracedDir<D> {
+    type WritableLeafDir = TracedDir<D::WritableLeafDir>;
+
+    fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self {
+        let source = self.source.writable_subdir(name.clone());
+        self.wrap(Operation::WritableSubdir, &name, source)
+    }
+
+    fn into_writable_leaf(self) -> Self::WritableLeafDir {
+        TracedDir {
+            source: self.source.into_writable_leaf(),
+            trace: self.trace,
+            path: self.path,
+        }
+    }
+
+    fn remove_subdir(&mut self, name: Cow<'static, str>) -> std::io::Result<()> {
+        let path = join(&self.path, &name);
+        let result = self.source.remove_subdir(name);
+        self.trace
+            .push_result(Operation::RemoveSubdir, &path, result)
+    }
+}
+
+pub struct TracedStorage<S> {
+    source: S,
+    trace: Trace,
+}
+
+impl<S: Storage> TracedStorage<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            trace: Trace::default(),
+        }
+    }
+
+    /// Keep at most this many records, dropping the oldest.
+    pub fn capacity(self, capacity: usize) -> Self {
+        self.trace.set_capacity(capacity);
+        self
+    }
+
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S: Storage> TracedStorage<S> {
+    fn wrap<D>(
+        &self,
+        operation: Operation,
+        category: Category,
+        result: Result<D, StorageError>,
+    ) -> Result<TracedDir<D>, StorageError> {
+        let outcome = match &result {
+            Ok(_) => Outcome::Ok,
+            Err(e) => Outcome::Failed(e.kind()),
+        };
+        self.trace.push(operation, category.name(), outcome);
+        Ok(TracedDir::new(result?, self.trace.clone(), category.name()))
+    }
+}
+
+impl<S: Storage> Storage for TracedStorage<S> {
+    type Dir = TracedDir<S::Dir>;
+    type WritableDir = TracedDir<S::WritableDir>;
+
+    fn dir(&self, category: Category) -> Result<Self::Dir, StorageError> {
+        self.wrap(Operation::Dir, category, self.source.dir(category))
+    }
+
+    fn writable_dir(&mut self, category: Category) -> Result<Self::WritableDir, StorageError> {
+        let result = self.source.writable_dir(category);
+        self.wrap(Operation::WritableDir, category, result)
+    }
+
+    async fn space(&self) -> std::io::Result<Space> {
+        let result = self.source.space().await;
+        self.trace.push_result(Operation::Space, "", result)
+    }
+
+    async fn request_persistence(&mut self) -> std::io::Result<bool> {
+        let result = self.source.request_persistence().await;
+        self.trace
+            .push_result(Operation::RequestPersistence, "", result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::generic::tests as generic_tests;
+    use super::*;
+    use crate::storage::faulty::FaultyStorage;
+    use crate::storage::MemoryStorage;
+
+    fn make_storage() -> TracedStorage<MemoryStorage> {
+        TracedStorage::new(MemoryStorage::new())
+    }
+
+    #[test]
+    fn text_file() {
+        generic_tests::text_file(make_storage());
+    }
+
+    #[test]
+    fn missing_file() {
+        generic_tests::missing_file(make_storage());
+    }
+
+    #[test]
+    fn commit_and_abort() {
+        generic_tests::commit_and_abort(make_storage());
+    }
+
+    #[test]
+    fn copy_and_move() {
+        generic_tests::copy_and_move(make_storage());
+    }
+
+    #[test]
+    fn listing() {
+        generic_tests::listing(make_storage());
+    }
+
+    #[test]
+    fn locking() {
+        generic_tests::locking(make_storage());
+    }
+
+    #[test]
+    fn records() {
+        let mut storage = make_storage();
+        let mut dir = storage.writable_data().unwrap();
+        let mut file = dir
+            .writable_subdir("saves".into())
+            .writable_file("1".into());
+        let mut write = file.write_text().unwrap();
+        write.write_all(b"level").unwrap();
+        write.flush().unwrap();
+        write.write_all(b" 1").unwrap();
+        write.commit().unwrap();
+        drop(write);
+        file.write_binary().unwrap().write_all(b"lost").unwrap();
+        assert!(file.exists().unwrap());
+        let mut got = String::new();
+        file.read_text().unwrap().read_to_string(&mut got).unwrap();
+        let target = &mut dir.writable_file("backup".into());
+        file.copy_to(target).unwrap();
+        dir.remove_subdir("saves".into()).unwrap();
+        assert_eq!(dir.entries().unwrap(), vec![Entry::file("backup")]);
+        assert_eq!(
+            storage.trace().take(),
+            vec![
+                Record::new(Operation::WritableDir, "data", Outcome::Ok),
+                Record::new(Operation::WritableSubdir, "data/saves", Outcome::Ok),
+                Record::new(Operation::WritableFile, "data/saves/1", Outcome::Ok),
+                Record::new(Operation::OpenWrite, "data/saves/1", Outcome::Ok),
+                Record::new(Operation::Write, "data/saves/1", Outcome::Bytes(5)),
+                Record::new(Operation::Flush, "data/saves/1", Outcome::Ok),
+                Record::new(Operation::Write, "data/saves/1", Outcome::Bytes(2)),
+                Record::new(Operation::Commit, "data/saves/1", Outcome::Ok),
+                Record::new(Operation::OpenWrite, "data/saves/1", Outcome::Ok),
+                Record::new(Operation::Write, "data/saves/1", Outcome::Bytes(4)),
+                Record::new(Operation::Abort, "data/saves/1", Outcome::Ok),
+                Record::new(Operation::Exists, "data/saves/1", Outcome::Exists(true)),
+                Record::new(Operation::OpenRead, "data/saves/1", Outcome::Ok),
+                Record::new(Operation::Read, "data/saves/1", Outcome::Bytes(7)),
+                Record::new(Operation::WritableFile, "data/backup", Outcome::Ok),
+                Record::new(
+                    Operation::CopyTo("data/backup".into()),
+                    "data/saves/1",
+                    Outcome::Ok
+                ),
+                Record::new(Operation::RemoveSubdir, "data/saves", Outcome::Ok),
+                Record::new(Operation::Entries, "data", Outcome::Entries(1)),
+            ]
+        );
+        assert!(storage.trace().records().is_empty());
+    }
+
+    #[test]
+    fn failures() {
+        let storage = TracedStorage::new(FaultyStorage::new(MemoryStorage::new()));
+        storage.source.faults().unavailable(Category::Config, true);
+        assert!(storage.config().is_err());
+        let file = storage.data().unwrap().file("missing".into());
+        assert!(file.read_text().is_err());
+        let records = storage.trace().take();
+        assert_eq!(
+            records[0],
+            Record::new(
+                Operation::Dir,
+                "config",
+                Outcome::Failed(std::io::ErrorKind::Other)
+            )
+        );
+        assert_eq!(
+            records[3],
+            Record::new(
+                Operation::OpenRead,
+                "data/missing",
+                Outcome::Failed(std::io::ErrorKind::NotFound)
+            )
+        );
+        assert_eq!(
+            records[3].to_string(),
+            "OpenRead data/missing: Failed(NotFound)"
+        );
+    }
+
+    #[test]
+    fn failed_read() {
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::BrokenPipe.into())
+            }
+        }
+        let storage = make_storage();
+        let file = storage.data().unwrap().file("a".into());
+        let mut read = file.read(b"ab".chain(Failing));
+        let mut got = Vec::new();
+        assert!(read.read_to_end(&mut got).is_err());
+        drop(read);
+        assert_eq!(
+            storage.trace().take()[2..],
+            [Record::new(
+                Operation::Read,
+                "data/a",
+                Outcome::Failed(std::io::ErrorKind::BrokenPipe)
+            )]
+        );
+    }
+
+    #[test]
+    fn capacity() {
+        let storage = make_storage().capacity(2);
+        let dir = storage.data().unwrap();
+        for name in ["a", "b", "c"] {
+            dir.file(name.into()).exists().unwrap();
+        }
+        assert_eq!(
+            storage.trace().take(),
+            vec![
+                Record::new(Operation::File, "data/c", Outcome::Ok),
+                Record::new(Operation::Exists, "data/c", Outcome::Exists(false)),
+            ]
+        );
+        storage.trace().set_capacity(0);
+        dir.file("d".into()).exists().unwrap();
+        assert!(storage.trace().records().is_empty());
+    }
+}
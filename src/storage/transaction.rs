@@ -0,0 +1,466 @@
+//! Writing and removing several files in a directory all at once, so that a crash part way
+//! through does not leave a mix of old and new files.
+//!
+//! New contents are first written to staging files next to the real ones, and files to remove are
+//! marked with empty files. Committing creates a file marking the transaction as committed, then
+//! moves the staging files into place, removes the marked files and finally removes the marker.
+//! Everything about a transaction is in file names rather than contents, so it is the same
+//! whichever storage wraps the backend. Moves are renames on standard and memory storage.
+//!
+//! If a transaction is interrupted, it is finished when it was marked as committed and otherwise
+//! its files are thrown away. Standard and web storage do this for the whole category the first
+//! time it is opened, so files are never read part way through a transaction after a crash. It
+//! also happens when the next transaction in the directory starts, or by calling [recover].
+//!
+//! Transaction files are named starting with `.dias-txn`. These names are reserved (see
+//! [crate::storage::validate_name]), and the files are left out of listings, copies and
+//! snapshots.
+//!
+//! ```rust
+//! use std::io::{Read, Write};
+//! use dias::storage::{Commit, Dir, File, MemoryStorage, Storage, WritableDir};
+//!
+//! let mut storage = MemoryStorage::new();
+//! let mut saves = storage.writable_data().unwrap();
+//! let mut transaction = saves.transaction().unwrap();
+//! let mut world = transaction.write_text("world".into()).unwrap();
+//! write!(world, "world 1").unwrap();
+//! world.commit().unwrap();
+//! let mut player = transaction.write_text("player".into()).unwrap();
+//! write!(player, "player 1").unwrap();
+//! player.commit().unwrap();
+//! transaction.remove("autosave".into()).unwrap();
+//! transaction.commit().unwrap();
+//!
+//! let mut world = String::new();
+//! saves.file("world".into()).read_text().unwrap().read_to_string(&mut world).unwrap();
+//! ```
+
+use crate::storage::error::StorageError;
+use crate::storage::generic::{
+    Category, Commit, EntryKind, File, WritableDir, WritableFile, WritableParentDir,
+};
+use crate::storage::name::{is_transaction_file, validate_name, with_transaction_files};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::sync::{Mutex, PoisonError};
+
+static STAGING_PREFIX: &str = ".dias-txn.";
+static REMOVAL_PREFIX: &str = ".dias-txn-remove.";
+/// Marks the transaction in the directory as committed.
+static COMMITTED_NAME: &str = ".dias-txn-committed";
+
+fn staging_name(name: &str) -> Cow<'static, str> {
+    format!("{}{}", STAGING_PREFIX, name).into()
+}
+
+fn removal_name(name: &str) -> Cow<'static, str> {
+    format!("{}{}", REMOVAL_PREFIX, name).into()
+}
+
+fn ignore_not_found(result: std::io::Result<()>) -> std::io::Result<()> {
+    match result {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Create an empty file.
+fn mark(dir: &mut impl WritableDir, name: Cow<'static, str>) -> std::io::Result<()> {
+    dir.writable_file(name).write_binary()?.commit()
+}
+
+enum Op {
+    Write(String),
+    Remove(String),
+}
+
+/// Carry out the changes of a committed transaction. Safe to repeat if interrupted.
+fn apply(dir: &mut impl WritableDir) -> std::io::Result<()> {
+    for entry in dir.entries()? {
+        if entry.kind != EntryKind::File {
+            continue;
+        }
+        if let Some(name) = entry.name.strip_prefix(STAGING_PREFIX) {
+            let mut target = dir.writable_file(name.to_string().into());
+            dir.writable_file(entry.name.clone().into())
+                .move_to(&mut target)?;
+        } else if let Some(name) = entry.name.strip_prefix(REMOVAL_PREFIX) {
+            ignore_not_found(dir.writable_file(name.to_string().into()).remove())?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove all transaction files, leaving the commit marker until last.
+fn clean_up(dir: &mut impl WritableDir) -> std::io::Result<()> {
+    for entry in dir.entries()? {
+        if is_transaction_file(&entry.name) && entry.name != COMMITTED_NAME {
+            ignore_not_found(dir.writable_file(entry.name.into()).remove())?;
+        }
+    }
+    ignore_not_found(dir.writable_file(COMMITTED_NAME.into()).remove())
+}
+
+/// Finish or throw away any transaction in the directory which was interrupted. Only needed for
+/// directories of backends which do not do this when opened, such as memory storage.
+pub fn recover(dir: &mut impl WritableDir) -> std::io::Result<()> {
+    with_transaction_files(|| {
+        if dir.file(COMMITTED_NAME.into()).exists()? {
+            apply(dir)?;
+        }
+        clean_up(dir)
+    })
+}
+
+/// Recover a directory and everything under it.
+fn recover_tree(dir: &mut impl WritableParentDir) -> std::io::Result<()> {
+    recover(dir)?;
+    for entry in dir.entries()? {
+        if entry.kind == EntryKind::Dir {
+            recover_tree(&mut dir.writable_subdir(entry.name.into()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Recovers each category of a storage the first time it is opened, for backends which keep files
+/// after the application exits.
+#[derive(Default)]
+pub(crate) struct RecoverOnOpen {
+    recovered: Mutex<BTreeSet<Category>>,
+}
+
+impl RecoverOnOpen {
+    pub(crate) fn open<D: WritableParentDir>(
+        &self,
+        category: Category,
+        outer_dir: impl FnOnce() -> Result<D, StorageError>,
+    ) -> Result<(), StorageError> {
+        let mut recovered = self
+            .recovered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !recovered.contains(&category) {
+            recover_tree(&mut outer_dir()?)?;
+            recovered.insert(category);
+        }
+        Ok(())
+    }
+}
+
+/// Changes to a directory which are made all at once by [Transaction::commit], or thrown away
+/// if the transaction is dropped first.
+pub struct Transaction<'a, D: WritableDir> {
+    dir: &'a mut D,
+    ops: Vec<Op>,
+}
+
+impl<'a, D: WritableDir> Transaction<'a, D> {
+    pub(crate) fn begin(dir: &'a mut D) -> Self {
+        Self {
+            dir,
+            ops: Vec::new(),
+        }
+    }
+
+    fn stage(&mut self, name: Cow<'static, str>) -> std::io::Result<D::WritableFile> {
+        validate_name(&name)?;
+        self.ops.retain(|op| match op {
+            Op::Write(other) | Op::Remove(other) => *other != name,
+        });
+        self.ops.push(Op::Write(name.to_string()));
+        with_transaction_files(|| {
+            ignore_not_found(self.dir.writable_file(removal_name(&name)).remove())?;
+            Ok(self.dir.writable_file(staging_name(&name)))
+        })
+    }
+
+    /// Write a file when the transaction is committed. The writer must be committed before the
+    /// transaction is.
+    pub fn write_text(
+        &mut self,
+        name: Cow<'static, str>,
+    ) -> std::io::Result<<D::WritableFile as WritableFile>::WriteText> {
+        self.stage(name)?.write_text()
+    }
+
+    /// Write a file when the transaction is committed. The writer must be committed before the
+    /// transaction is.
+    pub fn write_binary(
+        &mut self,
+        name: Cow<'static, str>,
+    ) -> std::io::Result<<D::WritableFile as WritableFile>::WriteBinary> {
+        self.stage(name)?.write_binary()
+    }
+
+    /// Remove a file when the transaction is committed.
+    pub fn remove(&mut self, name: Cow<'static, str>) -> std::io::Result<()> {
+        validate_name(&name)?;
+        self.ops.retain(|op| match op {
+            Op::Write(other) | Op::Remove(other) => *other != name,
+        });
+        self.ops.push(Op::Remove(name.to_string()));
+        with_transaction_files(|| {
+            ignore_not_found(self.dir.writable_file(staging_name(&name)).remove())?;
+            mark(self.dir, removal_name(&name))
+        })
+    }
+
+    /// Make all the changes. Fails without changing anything if a file was written without
+    /// committing its writer. If this fails part way through, the changes are finished when the
+    /// storage is next opened, when the next transaction starts or by [recover].
+    pub fn commit(mut self) -> std::io::Result<()> {
+        with_transaction_files(|| self.finish())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        for op in &self.ops {
+            if let Op::Write(name) = op {
+                if !self.dir.file(staging_name(name)).exists()? {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("writer for {} was not committed", name),
+                    ));
+                }
+            }
+        }
+        mark(self.dir, COMMITTED_NAME.into())?;
+        self.ops.clear();
+        apply(self.dir)?;
+        clean_up(self.dir)
+    }
+}
+
+impl<D: WritableDir> Drop for Transaction<'_, D> {
+    fn drop(&mut self) {
+        // Staging files are thrown away unless the transaction was committed.
+        if !self.ops.is_empty() {
+            let _ = with_transaction_files(|| clean_up(self.dir));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{Dir, Entry, MemoryStorage, Storage};
+    use std::io::{Read, Write};
+
+    fn read(dir: &impl Dir, name: &'static str) -> Option<String> {
+        let file = dir.file(name.into());
+        if !file.exists().unwrap() {
+            return None;
+        }
+        let mut got = String::new();
+        file.read_text().unwrap().read_to_string(&mut got).unwrap();
+        Some(got)
+    }
+
+    fn write(dir: &mut impl WritableDir, name: &'static str, contents: &str) {
+        let mut write = dir.writable_file(name.into()).write_text().unwrap();
+        write.write_all(contents.as_bytes()).unwrap();
+        write.commit().unwrap();
+    }
+
+    #[test]
+    fn commit() {
+        let mut storage = MemoryStorage::new();
+        let mut dir = storage.writable_data().unwrap();
+        write(&mut dir, "world", "old world");
+        write(&mut dir, "autosave", "old autosave");
+        let mut transaction = dir.transaction().unwrap();
+        let mut write = transaction.write_text("world".into()).unwrap();
+        write.write_all(b"new world").unwrap();
+        write.commit().unwrap();
+        let mut write = transaction.write_binary("player".into()).unwrap();
+        write.write_all(b"new player").unwrap();
+        write.commit().unwrap();
+        transaction.remove("autosave".into()).unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(read(&dir, "world").as_deref(), Some("new world"));
+        assert_eq!(read(&dir, "player").as_deref(), Some("new player"));
+        assert_eq!(read(&dir, "autosave"), None);
+        assert_eq!(
+            dir.entries().unwrap(),
+            vec![Entry::file("player"), Entry::file("world")]
+        );
+    }
+
+    #[test]
+    fn dropped() {
+        let mut storage = MemoryStorage::new();
+        let mut dir = storage.writable_data().unwrap();
+        write(&mut dir, "world", "old world");
+        let mut transaction = dir.transaction().unwrap();
+        let mut write = transaction.write_text("world".into()).unwrap();
+        write.write_all(b"new world").unwrap();
+        write.commit().unwrap();
+        transaction.remove("world".into()).unwrap();
+        let mut write = transaction.write_text("player".into()).unwrap();
+        write.write_all(b"new player").unwrap();
+        write.commit().unwrap();
+        drop(transaction);
+        assert_eq!(read(&dir, "world").as_deref(), Some("old world"));
+        assert_eq!(dir.entries().unwrap(), vec![Entry::file("world")]);
+    }
+
+    #[test]
+    fn uncommitted_write() {
+        let mut storage = MemoryStorage::new();
+        let mut dir = storage.writable_data().unwrap();
+        write(&mut dir, "world", "old world");
+        let mut transaction = dir.transaction().unwrap();
+        transaction.remove("autosave".into()).unwrap();
+        let mut write = transaction.write_text("world".into()).unwrap();
+        write.write_all(b"new world").unwrap();
+        drop(write);
+        assert_eq!(
+            transaction.commit().unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+        assert_eq!(read(&dir, "world").as_deref(), Some("old world"));
+        assert_eq!(dir.entries().unwrap(), vec![Entry::file("world")]);
+    }
+
+    #[test]
+    fn recovery() {
+        let mut storage = MemoryStorage::new();
+        let mut dir = storage.writable_data().unwrap();
+        write(&mut dir, "world", "old world");
+        write(&mut dir, "autosave", "old autosave");
+
+        // Interrupted before committing.
+        with_transaction_files(|| {
+            write(&mut dir, ".dias-txn.world", "new world");
+            write(&mut dir, ".dias-txn-remove.autosave", "");
+        });
+        recover(&mut dir).unwrap();
+        assert_eq!(read(&dir, "world").as_deref(), Some("old world"));
+        assert_eq!(
+            dir.entries().unwrap(),
+            vec![Entry::file("autosave"), Entry::file("world")]
+        );
+
+        // Interrupted part way through moving files into place.
+        write(&mut dir, "player", "new player");
+        with_transaction_files(|| {
+            write(&mut dir, ".dias-txn.world", "new world");
+            write(&mut dir, ".dias-txn-remove.autosave", "");
+            write(&mut dir, ".dias-txn-committed", "");
+        });
+        recover(&mut dir).unwrap();
+        assert_eq!(read(&dir, "world").as_deref(), Some("new world"));
+        assert_eq!(read(&dir, "player").as_deref(), Some("new player"));
+        assert_eq!(read(&dir, "autosave"), None);
+        assert_eq!(
+            dir.entries().unwrap(),
+            vec![Entry::file("player"), Entry::file("world")]
+        );
+    }
+
+    #[test]
+    fn hidden() {
+        use crate::storage::copy::{copy_storage, CopyOptions};
+        use crate::storage::MemorySnapshot;
+        let mut storage = MemoryStorage::new();
+        let mut dir = storage.writable_data().unwrap();
+        write(&mut dir, "autosave", "old autosave");
+        let mut transaction = dir.transaction().unwrap();
+        let mut write = transaction.write_text("world".into()).unwrap();
+        write.write_all(b"new world").unwrap();
+        write.commit().unwrap();
+        transaction.remove("autosave".into()).unwrap();
+
+        let expected = MemorySnapshot::from_iter([("data/autosave", "old autosave")]);
+        assert_eq!(storage.snapshot().unwrap(), expected);
+        let mut copy = MemoryStorage::new();
+        copy_storage(&storage, &mut copy, &CopyOptions::new()).unwrap();
+        assert_eq!(copy.snapshot().unwrap(), expected);
+        assert_eq!(
+            storage.data().unwrap().entries().unwrap(),
+            vec![Entry::file("autosave")]
+        );
+        let staged = storage
+            .writable_data()
+            .unwrap()
+            .writable_file(".dias-txn.world".into());
+        assert_eq!(
+            staged.exists().unwrap_err().kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+        transaction.commit().unwrap();
+    }
+
+    #[test]
+    fn interrupted_commit() {
+        use crate::storage::faulty::{FaultyStorage, Operation};
+        let mut storage = FaultyStorage::new(MemoryStorage::new());
+        let mut dir = storage.writable_data().unwrap();
+        write(&mut dir, "world", "old world");
+        write(&mut dir, "player", "old player");
+        let mut transaction = dir.transaction().unwrap();
+        for (name, contents) in [("player", "new player"), ("world", "new world")] {
+            let mut write = transaction.write_text(name.into()).unwrap();
+            write.write_all(contents.as_bytes()).unwrap();
+            write.commit().unwrap();
+        }
+        // Marking the transaction as committed and moving the first file work, then moving the second fails.
+        storage
+            .faults()
+            .fail_after(Operation::Write, 2, std::io::ErrorKind::Other);
+        transaction.commit().unwrap_err();
+        storage.faults().clear();
+        assert_eq!(read(&dir, "player").as_deref(), Some("new player"));
+        assert_eq!(read(&dir, "world").as_deref(), Some("old world"));
+
+        drop(dir.transaction().unwrap());
+        assert_eq!(read(&dir, "world").as_deref(), Some("new world"));
+        assert_eq!(
+            dir.entries().unwrap(),
+            vec![Entry::file("player"), Entry::file("world")]
+        );
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn recovered_on_open() {
+        use crate::storage::standard::Storage as StandardStorage;
+        use crate::storage::ParentDir;
+        let temp = tempfile::tempdir().unwrap();
+        let saves = temp.path().join("data").join("saves");
+        std::fs::create_dir_all(&saves).unwrap();
+        for (name, contents) in [
+            ("world", "old world"),
+            ("autosave", "old autosave"),
+            (".dias-txn.world", "new world"),
+            (".dias-txn-remove.autosave", ""),
+            (".dias-txn-committed", ""),
+        ] {
+            std::fs::write(saves.join(name), contents).unwrap();
+        }
+        let storage = StandardStorage::with_root(temp.path());
+        let dir = storage.data().unwrap().subdir("saves".into());
+        assert_eq!(read(&dir, "world").as_deref(), Some("new world"));
+        assert_eq!(dir.entries().unwrap(), vec![Entry::file("world")]);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn standard() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut storage = crate::storage::StorageBuilder::new("a")
+            .root(temp.path())
+            .build()
+            .unwrap();
+        let mut dir = storage.writable_data().unwrap();
+        write(&mut dir, "world", "old world");
+        let mut transaction = dir.transaction().unwrap();
+        let mut write = transaction.write_text("world".into()).unwrap();
+        write.write_all(b"new world").unwrap();
+        write.commit().unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(read(&dir, "world").as_deref(), Some("new world"));
+        assert_eq!(dir.entries().unwrap(), vec![Entry::file("world")]);
+    }
+}
@@ -0,0 +1,106 @@
+//! Helpers for watching for changes.
+
+use crate::storage::error::poisoned;
+use crate::storage::generic::{Change, ChangeKind, Watcher};
+use crate::storage::name::is_transaction_file;
+use std::sync::{Arc, Mutex, Weak};
+
+/// Add a change to those not yet taken, combining it with any earlier change to the same file.
+pub fn push_change(changes: &mut Vec<Change>, name: &str, kind: ChangeKind) {
+    use ChangeKind::*;
+    let Some(i) = changes.iter().position(|c| c.name == name) else {
+        changes.push(Change::new(name, kind));
+        return;
+    };
+    match (changes[i].kind, kind) {
+        (Created, Removed) => {
+            changes.remove(i);
+        }
+        (Created, _) | (Removed, Removed) => {}
+        (Removed, _) => changes[i].kind = Modified,
+        (Modified, kind) => changes[i].kind = kind,
+    }
+}
+
+/// What a watcher is interested in, for backends which store files under flat keys separated by
+/// `/`.
+pub enum Scope {
+    /// Everything under a directory prefix, which ends with `/`.
+    Dir(String),
+    File(String),
+}
+
+impl Scope {
+    /// Name of a key as reported to the watcher, if the watcher is interested in it. Changes to
+    /// transaction files are left out, like they are from listings.
+    pub fn name<'a>(&self, key: &'a str) -> Option<&'a str> {
+        if is_transaction_file(key.rsplit('/').next().unwrap_or(key)) {
+            return None;
+        }
+        match self {
+            Self::Dir(prefix) => key.strip_prefix(prefix.as_str()).filter(|n| !n.is_empty()),
+            Self::File(path) => (key == path).then(|| key.rsplit('/').next().unwrap_or(key)),
+        }
+    }
+}
+
+type Changes = Mutex<Vec<Change>>;
+
+fn lock(changes: &Changes) -> std::io::Result<std::sync::MutexGuard<'_, Vec<Change>>> {
+    changes.lock().map_err(poisoned)
+}
+
+/// Passes on changes made in this process, for backends where nothing else can change the
+/// stored files.
+#[derive(Default)]
+pub struct Notifier {
+    watches: Mutex<Vec<(Scope, Weak<Changes>)>>,
+}
+
+impl Notifier {
+    pub fn watch(&self, scope: Scope) -> std::io::Result<Watcher> {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let mut watches = self.watches.lock().map_err(poisoned)?;
+        watches.retain(|(_, changes)| changes.strong_count() > 0);
+        watches.push((scope, Arc::downgrade(&changes)));
+        Ok(Watcher::new(move || {
+            Ok(std::mem::take(&mut *lock(&changes)?))
+        }))
+    }
+
+    pub fn notify(&self, key: &str, kind: ChangeKind) -> std::io::Result<()> {
+        let watches = self.watches.lock().map_err(poisoned)?;
+        for (scope, changes) in watches.iter() {
+            if let (Some(name), Some(changes)) = (scope.name(key), changes.upgrade()) {
+                push_change(&mut *lock(&changes)?, name, kind);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combining() {
+        use ChangeKind::*;
+        for (kinds, combined) in [
+            (&[Created, Modified][..], Some(Created)),
+            (&[Created, Removed], None),
+            (&[Created, Removed, Created], Some(Created)),
+            (&[Modified, Modified], Some(Modified)),
+            (&[Modified, Removed], Some(Removed)),
+            (&[Removed, Created], Some(Modified)),
+            (&[Removed, Created, Removed], Some(Removed)),
+        ] {
+            let mut changes = Vec::new();
+            for kind in kinds {
+                push_change(&mut changes, "a", *kind);
+            }
+            let expected: Vec<_> = combined.map(|k| Change::new("a", k)).into_iter().collect();
+            assert_eq!(changes, expected, "{:?}", kinds);
+        }
+    }
+}
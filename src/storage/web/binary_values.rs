@@ -1,24 +1,93 @@
+use super::metadata::{self, Kind, Sidecar};
+use super::space;
+use crate::storage::error::StorageError;
+use crate::storage::generic::{finished_writer, Commit};
 use std::io::{Cursor, Read, Write};
 use web_sys::Storage as WebStorage;
 
+// Binary values are packed 15 bits to a char, using only chars in U+0000..=U+7FFF so that each
+// takes a single UTF-16 code unit without needing surrogates. The length in the metadata tells
+// how much of the last char is padding.
+const PACKED_BITS: u32 = 15;
+const PACKED_MASK: u32 = (1 << PACKED_BITS) - 1;
+
+fn encode(bytes: &[u8]) -> String {
+    let mut chars = Vec::with_capacity(bytes.len() * 8 / PACKED_BITS as usize + 1);
+    let mut bits: u32 = 0;
+    let mut num_bits = 0;
+    for byte in bytes {
+        bits = (bits << 8) | *byte as u32;
+        num_bits += 8;
+        if num_bits >= PACKED_BITS {
+            num_bits -= PACKED_BITS;
+            chars.push(bits >> num_bits);
+            bits &= (1 << num_bits) - 1;
+        }
+    }
+    if num_bits > 0 {
+        chars.push((bits << (PACKED_BITS - num_bits)) & PACKED_MASK);
+    }
+    chars
+        .into_iter()
+        .map(|c| char::from_u32(c).expect("should be below surrogate range"))
+        .collect()
+}
+
+fn decode(value: &str, len: u64) -> std::io::Result<Vec<u8>> {
+    let corrupt = || StorageError::corrupt("invalid packed binary value").into();
+    let mut bytes = Vec::with_capacity(value.len() * PACKED_BITS as usize / 8);
+    let mut bits: u32 = 0;
+    let mut num_bits = 0;
+    for c in value.chars() {
+        let c = c as u32;
+        if c > PACKED_MASK {
+            return Err(corrupt());
+        }
+        bits = (bits << PACKED_BITS) | c;
+        num_bits += PACKED_BITS;
+        while num_bits >= 8 {
+            num_bits -= 8;
+            bytes.push((bits >> num_bits) as u8);
+        }
+        bits &= (1 << num_bits) - 1;
+    }
+    // The padding in the last char can make up one more byte.
+    if (bytes.len() as u64) < len || bytes.len() as u64 > len + 1 {
+        return Err(corrupt());
+    }
+    bytes.truncate(len as usize);
+    Ok(bytes)
+}
+
+/// The contents of a stored value, however it was written. Values from older versions, which
+/// have no metadata, are read as the given kind.
+pub fn contents(web_storage: &WebStorage, key: &str, kind: Kind) -> std::io::Result<Vec<u8>> {
+    let value = web_storage
+        .get_item(key)
+        .unwrap_or(None)
+        .ok_or_else(|| std::io::Error::from(StorageError::NotFound))?;
+    match metadata::sidecar(web_storage, key)? {
+        Some(Sidecar {
+            kind: Kind::Binary,
+            len,
+            ..
+        }) => decode(&value, len),
+        Some(_) => Ok(value.into_bytes()),
+        // Older versions stored binary values as one char per byte.
+        None if kind == Kind::Binary => Ok(value.chars().map(|c| c as u8).collect()),
+        None => Ok(value.into_bytes()),
+    }
+}
+
 pub struct BinaryStorageReader {
     cursor: Cursor<Vec<u8>>,
 }
 
 impl BinaryStorageReader {
     pub fn new(web_storage: &WebStorage, key: &str) -> std::io::Result<Self> {
-        let value = web_storage.get_item(key).unwrap_or(None);
-        if let Some(value) = value {
-            let value = value.chars().map(|c| c as u8).collect();
-            Ok(Self {
-                cursor: Cursor::new(value),
-            })
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "cannot find stored value",
-            ))
-        }
+        Ok(Self {
+            cursor: Cursor::new(contents(web_storage, key, Kind::Binary)?),
+        })
     }
 }
 
@@ -28,10 +97,11 @@ impl Read for BinaryStorageReader {
     }
 }
 
+/// Collects contents which replace the stored value when committed.
 pub struct BinaryStorageWriter {
     web_storage: WebStorage,
     key: String,
-    buf: Vec<u8>,
+    buf: Option<Vec<u8>>,
 }
 
 impl BinaryStorageWriter {
@@ -39,26 +109,58 @@ impl BinaryStorageWriter {
         Ok(Self {
             web_storage: web_storage.clone(),
             key: key.to_string(),
-            buf: Vec::new(),
+            buf: Some(Vec::new()),
         })
     }
 }
 
 impl Write for BinaryStorageWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.buf.write(buf)
+        self.buf.as_mut().ok_or_else(finished_writer)?.write(buf)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        let value = self.buf.iter().map(|b| *b as char).collect::<String>();
+        Ok(())
+    }
+}
+
+impl Commit for BinaryStorageWriter {
+    fn commit(&mut self) -> std::io::Result<()> {
+        let buf = self.buf.take().ok_or_else(finished_writer)?;
+        let value = encode(&buf);
         self.web_storage
             .set_item(&self.key, &value)
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "cannot store value"))
+            .map_err(space::store_failed)?;
+        metadata::store(&self.web_storage, &self.key, buf.len(), Kind::Binary)
+    }
+
+    fn abort(&mut self) {
+        self.buf = None;
     }
 }
 
-impl Drop for BinaryStorageWriter {
-    fn drop(&mut self) {
-        let _ = self.flush();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    #[wasm_bindgen_test]
+    fn round_trip() {
+        for len in 0..64usize {
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 97 + 13) as u8).collect();
+            let encoded = encode(&bytes);
+            assert!(encoded.chars().count() <= (len * 8).div_ceil(15));
+            assert_eq!(decode(&encoded, len as u64).unwrap(), bytes);
+        }
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode(&encode(&bytes), 256).unwrap(), bytes);
+    }
+
+    #[wasm_bindgen_test]
+    fn wrong_length() {
+        let encoded = encode(&[1, 2, 3, 4]);
+        assert!(decode(&encoded, 3).is_err());
+        assert!(decode(&encoded, 6).is_err());
+        assert!(decode("\u{E000}", 0).is_err());
     }
 }
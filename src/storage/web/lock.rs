@@ -0,0 +1,72 @@
+//! Best-effort locks, as leases kept in a sidecar key. Web storage can not compare and set in one
+//! step, so two tabs locking at the same moment can both succeed.
+
+use super::space;
+use crate::storage::error::StorageError;
+use crate::storage::generic::FileLock;
+use crate::storage::time::now;
+use std::time::{Duration, SystemTime};
+use web_sys::Storage as WebStorage;
+
+/// Prefix for lease keys. Cannot clash with value keys, which all start with a directory name.
+static LOCK_PREFIX: &str = "lock:";
+
+/// How long a lease lasts without being renewed, so that locks from closed tabs run out.
+const LEASE: Duration = Duration::from_secs(30);
+
+fn millis(time: SystemTime) -> u128 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+/// The holder and expiry time of the lease on a key, if there is one.
+fn lease(web_storage: &WebStorage, lock_key: &str) -> std::io::Result<Option<(String, u128)>> {
+    let value = web_storage
+        .get_item(lock_key)
+        .map_err(|_| std::io::Error::from(StorageError::backend("cannot get lock")))?;
+    Ok(value.and_then(|value| {
+        let (holder, expires) = value.split_once(' ')?;
+        Some((holder.to_string(), expires.parse().ok()?))
+    }))
+}
+
+fn store(web_storage: &WebStorage, lock_key: &str, holder: &str) -> std::io::Result<()> {
+    let expires = millis(now() + LEASE);
+    web_storage
+        .set_item(lock_key, &format!("{} {}", holder, expires))
+        .map_err(space::store_failed)
+}
+
+pub fn try_lock(web_storage: &WebStorage, key: &str) -> std::io::Result<FileLock> {
+    let lock_key = format!("{}{}", LOCK_PREFIX, key);
+    if let Some((_, expires)) = lease(web_storage, &lock_key)? {
+        if expires > millis(now()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::WouldBlock,
+                "file is locked",
+            ));
+        }
+    }
+    let holder = format!("{:x}", (js_sys::Math::random() * u64::MAX as f64) as u64);
+    store(web_storage, &lock_key, &holder)?;
+    let renew = {
+        let (web_storage, lock_key, holder) =
+            (web_storage.clone(), lock_key.clone(), holder.clone());
+        move || match lease(&web_storage, &lock_key)? {
+            Some((other, _)) if other != holder => {
+                Err(std::io::Error::from(StorageError::backend("lock was lost")))
+            }
+            _ => store(&web_storage, &lock_key, &holder),
+        }
+    };
+    let web_storage = web_storage.clone();
+    let release = move || {
+        if let Ok(Some((other, _))) = lease(&web_storage, &lock_key) {
+            if other == holder {
+                let _ = web_storage.remove_item(&lock_key);
+            }
+        }
+    };
+    Ok(FileLock::new(renew, release))
+}
@@ -0,0 +1,124 @@
+//! Metadata for stored values is kept in a sidecar key next to each value, since web storage
+//! only holds strings.
+
+use super::space;
+use crate::storage::error::StorageError;
+use crate::storage::generic::Metadata;
+use crate::storage::time::now;
+use std::time::{Duration, SystemTime};
+use web_sys::Storage as WebStorage;
+
+/// Prefix for sidecar keys. Cannot clash with value keys, which all start with a directory name.
+static META_PREFIX: &str = "meta:";
+static TEXT: &str = "text";
+static BINARY: &str = "binary";
+
+fn meta_key(key: &str) -> String {
+    format!("{}{}", META_PREFIX, key)
+}
+
+/// How a value was written, which decides how it is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Text,
+    Binary,
+}
+
+/// What the sidecar says about a value.
+pub struct Sidecar {
+    /// Length of the contents in bytes, before packing binary values.
+    pub len: u64,
+    modified: u64,
+    pub kind: Kind,
+}
+
+pub fn store(web_storage: &WebStorage, key: &str, len: usize, kind: Kind) -> std::io::Result<()> {
+    let modified = now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let kind = match kind {
+        Kind::Text => TEXT,
+        Kind::Binary => BINARY,
+    };
+    web_storage
+        .set_item(&meta_key(key), &format!("{} {} {}", len, modified, kind))
+        .map_err(space::store_failed)
+}
+
+/// Values stored by older versions have no sidecar.
+pub fn sidecar(web_storage: &WebStorage, key: &str) -> std::io::Result<Option<Sidecar>> {
+    let sidecar = web_storage
+        .get_item(&meta_key(key))
+        .map_err(|_| std::io::Error::from(StorageError::backend("cannot get metadata")))?;
+    let Some(sidecar) = sidecar else {
+        return Ok(None);
+    };
+    parse(&sidecar)
+        .map(Some)
+        .ok_or_else(|| StorageError::corrupt("invalid metadata").into())
+}
+
+fn parse(sidecar: &str) -> Option<Sidecar> {
+    let mut parts = sidecar.split(' ');
+    let len = parts.next()?.parse().ok()?;
+    let modified = parts.next()?.parse().ok()?;
+    let kind = match parts.next()? {
+        kind if kind == TEXT => Kind::Text,
+        kind if kind == BINARY => Kind::Binary,
+        _ => return None,
+    };
+    Some(Sidecar {
+        len,
+        modified,
+        kind,
+    })
+}
+
+/// Whether a value from an older version, without a sidecar, was stored as binary. Older versions
+/// stored binary values as one char per byte, so any value with larger chars must be text. Other
+/// values could be either, and are taken as binary.
+pub fn is_old_binary(value: &str) -> bool {
+    value.chars().all(|c| c as u32 <= 0xFF)
+}
+
+pub fn load(web_storage: &WebStorage, key: &str) -> std::io::Result<Metadata> {
+    let value = web_storage
+        .get_item(key)
+        .map_err(|_| std::io::Error::from(StorageError::backend("cannot get value")))?
+        .ok_or_else(|| std::io::Error::from(StorageError::NotFound))?;
+    Ok(match sidecar(web_storage, key)? {
+        Some(sidecar) => Metadata {
+            len: sidecar.len,
+            modified: Some(SystemTime::UNIX_EPOCH + Duration::from_millis(sidecar.modified)),
+        },
+        None if is_old_binary(&value) => Metadata {
+            len: value.chars().count() as u64,
+            modified: None,
+        },
+        None => Metadata {
+            len: value.len() as u64,
+            modified: None,
+        },
+    })
+}
+
+/// Move the sidecar along with a value, keeping its modification time.
+pub fn rename(web_storage: &WebStorage, from: &str, to: &str) -> std::io::Result<()> {
+    let sidecar = web_storage
+        .get_item(&meta_key(from))
+        .map_err(|_| std::io::Error::from(StorageError::backend("cannot get metadata")))?;
+    match sidecar {
+        Some(sidecar) => web_storage
+            .set_item(&meta_key(to), &sidecar)
+            .map_err(|_| std::io::Error::from(StorageError::backend("cannot store metadata")))?,
+        None => remove(web_storage, to)?,
+    }
+    remove(web_storage, from)
+}
+
+pub fn remove(web_storage: &WebStorage, key: &str) -> std::io::Result<()> {
+    web_storage
+        .remove_item(&meta_key(key))
+        .map_err(|_| std::io::Error::from(StorageError::backend("cannot remove metadata")))
+}
@@ -1,5 +1,9 @@
 mod binary_values;
+mod lock;
+mod metadata;
+pub mod space;
 mod storage;
 mod text_values;
+mod watch;
 
 pub use storage::Storage;
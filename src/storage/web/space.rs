@@ -0,0 +1,45 @@
+//! Browser-wide storage quota, shared by the web backends.
+
+use crate::storage::error::StorageError;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{StorageEstimate, StorageManager};
+
+fn storage_manager() -> std::io::Result<StorageManager> {
+    Ok(web_sys::window()
+        .ok_or_else(|| std::io::Error::from(StorageError::backend("no window")))?
+        .navigator()
+        .storage())
+}
+
+/// Error for a value the browser refused to store, which in practice means the quota was exceeded.
+pub fn store_failed(_error: JsValue) -> std::io::Error {
+    StorageError::QuotaExceeded.into()
+}
+
+/// Estimate how many more bytes the browser will let this origin store.
+pub async fn available() -> std::io::Result<Option<u64>> {
+    let cannot_estimate =
+        |_| std::io::Error::from(StorageError::backend("cannot estimate storage"));
+    let promise = storage_manager()?.estimate().map_err(cannot_estimate)?;
+    let estimate: StorageEstimate = JsFuture::from(promise)
+        .await
+        .map_err(cannot_estimate)?
+        .into();
+    Ok(match (estimate.get_quota(), estimate.get_usage()) {
+        (Some(quota), Some(usage)) => Some((quota - usage).max(0.0) as u64),
+        _ => None,
+    })
+}
+
+/// Ask the browser not to evict this origin's storage.
+pub async fn persist() -> std::io::Result<bool> {
+    let cannot_persist =
+        |_| std::io::Error::from(StorageError::backend("cannot request persistence"));
+    let promise = storage_manager()?.persist().map_err(cannot_persist)?;
+    Ok(JsFuture::from(promise)
+        .await
+        .map_err(cannot_persist)?
+        .as_bool()
+        .unwrap_or(false))
+}
@@ -1,6 +1,13 @@
-use super::super::OuterDirectoryError;
+use super::super::{Category, Entry, FileLock, Metadata, Space, Watcher};
 use super::binary_values::{BinaryStorageReader, BinaryStorageWriter};
 use super::text_values::{TextStorageReader, TextStorageWriter};
+use super::watch::watch;
+use super::{lock, metadata, space};
+use crate::storage::error::StorageError;
+use crate::storage::keys::{entries_under, join};
+use crate::storage::name::InvalidNameError;
+use crate::storage::transaction::RecoverOnOpen;
+use crate::storage::watch::Scope;
 use std::borrow::Cow;
 use std::error::Error;
 use std::fmt;
@@ -26,14 +33,40 @@ impl Error for WebStorageAvailabilityError {}
 fn exists(web_storage: &WebStorage, path: &str) -> std::io::Result<bool> {
     Ok(web_storage
         .get_item(path)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "cannot get value"))?
+        .map_err(|_| std::io::Error::from(StorageError::backend("cannot get value")))?
         .is_some())
 }
 
+fn get(web_storage: &WebStorage, path: &str) -> std::io::Result<String> {
+    web_storage
+        .get_item(path)
+        .map_err(|_| std::io::Error::from(StorageError::backend("cannot get value")))?
+        .ok_or_else(|| std::io::Error::from(StorageError::NotFound))
+}
+
+fn set(web_storage: &WebStorage, path: &str, value: &str) -> std::io::Result<()> {
+    web_storage
+        .set_item(path, value)
+        .map_err(space::store_failed)
+}
+
+fn keys(web_storage: &WebStorage) -> std::io::Result<Vec<String>> {
+    let cannot_list = || std::io::Error::from(StorageError::backend("cannot list keys"));
+    let len = web_storage.length().map_err(|_| cannot_list())?;
+    let mut keys = Vec::with_capacity(len as usize);
+    for i in 0..len {
+        if let Some(key) = web_storage.key(i).map_err(|_| cannot_list())? {
+            keys.push(key);
+        }
+    }
+    Ok(keys)
+}
+
 fn remove(web_storage: &WebStorage, path: &str) -> std::io::Result<()> {
     web_storage
         .remove_item(path)
-        .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "cannot remove value"))
+        .map_err(|_| std::io::Error::from(StorageError::backend("cannot remove value")))?;
+    metadata::remove(web_storage, path)
 }
 
 pub struct ReadOnly;
@@ -42,19 +75,26 @@ pub struct ReadWrite;
 pub struct File<R> {
     _phantom: PhantomData<R>,
     web_storage: WebStorage,
-    path: String,
+    path: Result<String, InvalidNameError>,
 }
 
 impl<R> File<R> {
-    fn new(parent_path: String, name: Cow<'static, str>, web_storage: WebStorage) -> Self {
-        let mut path = parent_path;
-        path.push_str(name.as_ref());
+    fn new(
+        parent_path: &Result<String, InvalidNameError>,
+        name: Cow<'static, str>,
+        web_storage: WebStorage,
+    ) -> Self {
+        let path = join(parent_path, &name);
         Self {
             _phantom: PhantomData,
             path,
             web_storage,
         }
     }
+
+    fn path(&self) -> std::io::Result<&String> {
+        self.path.as_ref().map_err(|e| e.clone().into())
+    }
 }
 
 impl<R> super::super::File for File<R> {
@@ -62,15 +102,23 @@ impl<R> super::super::File for File<R> {
     type ReadBinary = BinaryStorageReader;
 
     fn exists(&self) -> std::io::Result<bool> {
-        exists(&self.web_storage, &self.path)
+        exists(&self.web_storage, self.path()?)
+    }
+
+    fn metadata(&self) -> std::io::Result<Metadata> {
+        metadata::load(&self.web_storage, self.path()?)
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        watch(&self.web_storage, Scope::File(self.path()?.clone()))
     }
 
     fn read_text(&self) -> std::io::Result<Self::ReadText> {
-        TextStorageReader::new(&self.web_storage, &self.path)
+        TextStorageReader::new(&self.web_storage, self.path()?)
     }
 
     fn read_binary(&self) -> std::io::Result<Self::ReadBinary> {
-        BinaryStorageReader::new(&self.web_storage, &self.path)
+        BinaryStorageReader::new(&self.web_storage, self.path()?)
     }
 }
 
@@ -79,42 +127,98 @@ impl super::super::WritableFile for File<ReadWrite> {
     type WriteBinary = BinaryStorageWriter;
 
     fn remove(&mut self) -> std::io::Result<()> {
-        remove(&self.web_storage, &self.path)
+        remove(&self.web_storage, self.path()?)
     }
 
     fn write_text(&mut self) -> std::io::Result<Self::WriteText> {
-        TextStorageWriter::new(&self.web_storage, &self.path)
+        TextStorageWriter::new(&self.web_storage, self.path()?)
     }
 
     fn write_binary(&mut self) -> std::io::Result<Self::WriteBinary> {
-        BinaryStorageWriter::new(&self.web_storage, &self.path)
+        BinaryStorageWriter::new(&self.web_storage, self.path()?)
+    }
+
+    fn location(&self) -> std::io::Result<String> {
+        self.path().cloned()
+    }
+
+    fn copy_to(&self, target: &mut Self) -> std::io::Result<()> {
+        let value = get(&self.web_storage, self.path()?)?;
+        set(&target.web_storage, target.path()?, &value)?;
+        match metadata::sidecar(&self.web_storage, self.path()?)? {
+            Some(sidecar) => metadata::store(
+                &target.web_storage,
+                target.path()?,
+                sidecar.len as usize,
+                sidecar.kind,
+            ),
+            None => metadata::remove(&target.web_storage, target.path()?),
+        }
+    }
+
+    fn move_to(&mut self, target: &mut Self) -> std::io::Result<()> {
+        if self.path()? == target.path()? {
+            return Ok(());
+        }
+        let value = get(&self.web_storage, self.path()?)?;
+        set(&target.web_storage, target.path()?, &value)?;
+        metadata::rename(&self.web_storage, self.path()?, target.path()?)?;
+        self.web_storage
+            .remove_item(self.path()?)
+            .map_err(|_| std::io::Error::from(StorageError::backend("cannot remove value")))
+    }
+
+    fn try_lock(&self) -> std::io::Result<FileLock> {
+        lock::try_lock(&self.web_storage, self.path()?)
     }
 }
 
 pub struct Dir<R> {
     _phantom: PhantomData<R>,
     web_storage: WebStorage,
-    path: String,
+    path: Result<String, InvalidNameError>,
 }
 
 impl<R> Dir<R> {
-    fn new(parent_path: String, name: Cow<'static, str>, web_storage: WebStorage) -> Self {
-        let mut path = parent_path;
-        path.push_str(name.as_ref());
-        path.push(SEP);
+    fn new(
+        parent_path: &Result<String, InvalidNameError>,
+        name: Cow<'static, str>,
+        web_storage: WebStorage,
+    ) -> Self {
+        let path = join(parent_path, &name).map(|mut path| {
+            path.push(SEP);
+            path
+        });
         Dir {
             _phantom: PhantomData,
             path,
             web_storage,
         }
     }
+
+    fn path(&self) -> std::io::Result<&String> {
+        self.path.as_ref().map_err(|e| e.clone().into())
+    }
 }
 
 impl<R> super::super::Dir for Dir<R> {
     type File = File<R>;
 
     fn file(&self, name: Cow<'static, str>) -> Self::File {
-        File::new(self.path.clone(), name, self.web_storage.clone())
+        File::new(&self.path, name, self.web_storage.clone())
+    }
+
+    fn entries(&self) -> std::io::Result<Vec<Entry>> {
+        let keys = keys(&self.web_storage)?;
+        Ok(entries_under(
+            self.path()?,
+            SEP,
+            keys.iter().map(|k| k.as_str()),
+        ))
+    }
+
+    fn watch(&self) -> std::io::Result<Watcher> {
+        watch(&self.web_storage, Scope::Dir(self.path()?.clone()))
     }
 }
 
@@ -122,7 +226,7 @@ impl<R> super::super::ParentDir for Dir<R> {
     type LeafDir = Dir<R>;
 
     fn subdir(&self, name: Cow<'static, str>) -> Self {
-        Self::new(self.path.clone(), name, self.web_storage.clone())
+        Self::new(&self.path, name, self.web_storage.clone())
     }
 
     fn into_leaf(self) -> Self::LeafDir {
@@ -134,7 +238,16 @@ impl super::super::WritableDir for Dir<ReadWrite> {
     type WritableFile = File<ReadWrite>;
 
     fn writable_file(&mut self, name: Cow<'static, str>) -> Self::WritableFile {
-        File::new(self.path.clone(), name, self.web_storage.clone())
+        File::new(&self.path, name, self.web_storage.clone())
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        for key in keys(&self.web_storage)? {
+            if key.starts_with(self.path()?) {
+                remove(&self.web_storage, &key)?;
+            }
+        }
+        Ok(())
     }
 }
 
@@ -142,80 +255,95 @@ impl super::super::WritableParentDir for Dir<ReadWrite> {
     type WritableLeafDir = Dir<ReadWrite>;
 
     fn writable_subdir(&mut self, name: Cow<'static, str>) -> Self {
-        Self::new(self.path.clone(), name, self.web_storage.clone())
+        Self::new(&self.path, name, self.web_storage.clone())
     }
 
     fn into_writable_leaf(self) -> Self::WritableLeafDir {
         self
     }
+
+    fn remove_subdir(&mut self, name: Cow<'static, str>) -> std::io::Result<()> {
+        super::super::WritableDir::clear(&mut self.writable_subdir(name))
+    }
 }
 
+/// Typical limit on local storage for an origin, in bytes.
+const LOCAL_STORAGE_LIMIT: u64 = 5 * 1024 * 1024;
+
 pub struct Storage {
     web_storage: WebStorage,
+    session_storage: Option<WebStorage>,
+    recover: RecoverOnOpen,
 }
 
 impl Storage {
+    /// Storage in local storage, except for [Category::Runtime] which is in session storage so
+    /// that it goes away with the tab.
     pub fn new() -> Result<Self, WebStorageAvailabilityError> {
+        let window = web_sys::window().ok_or(WebStorageAvailabilityError::NoWindow)?;
         Ok(Self {
-            web_storage: web_sys::window()
-                .ok_or(WebStorageAvailabilityError::NoWindow)?
+            web_storage: window
                 .local_storage()
                 .map_err(|_| WebStorageAvailabilityError::NoLocalStorage)?
                 .ok_or(WebStorageAvailabilityError::NoLocalStorage)?,
+            session_storage: window.session_storage().ok().flatten(),
+            recover: RecoverOnOpen::default(),
         })
     }
-}
 
-impl super::super::Storage for Storage {
-    type Dir = Dir<ReadOnly>;
-    type WritableDir = Dir<ReadWrite>;
-
-    fn data(&self) -> Result<Self::Dir, OuterDirectoryError> {
-        Ok(Dir::new(
-            "".to_string(),
-            "data".into(),
-            self.web_storage.clone(),
-        ))
+    fn outer_dir<R>(&self, category: Category) -> Result<Dir<R>, StorageError> {
+        self.recover
+            .open(category, || self.unrecovered_dir::<ReadWrite>(category))?;
+        self.unrecovered_dir(category)
     }
 
-    fn config(&self) -> Result<Self::Dir, OuterDirectoryError> {
+    fn unrecovered_dir<R>(&self, category: Category) -> Result<Dir<R>, StorageError> {
+        let web_storage = match category {
+            Category::Runtime => self
+                .session_storage
+                .as_ref()
+                .ok_or_else(|| StorageError::backend("no session storage"))?,
+            _ => &self.web_storage,
+        };
         Ok(Dir::new(
-            "".to_string(),
-            "config".into(),
-            self.web_storage.clone(),
+            &Ok("".to_string()),
+            category.name().into(),
+            web_storage.clone(),
         ))
     }
+}
 
-    fn cache(&self) -> Result<Self::Dir, OuterDirectoryError> {
-        Ok(Dir::new(
-            "".to_string(),
-            "cache".into(),
-            self.web_storage.clone(),
-        ))
+impl super::super::Storage for Storage {
+    type Dir = Dir<ReadOnly>;
+    type WritableDir = Dir<ReadWrite>;
+
+    fn dir(&self, category: Category) -> Result<Self::Dir, StorageError> {
+        self.outer_dir(category)
     }
 
-    fn writable_data(&mut self) -> Result<Self::WritableDir, OuterDirectoryError> {
-        Ok(Dir::new(
-            "".to_string(),
-            "data".into(),
-            self.web_storage.clone(),
-        ))
+    fn writable_dir(&mut self, category: Category) -> Result<Self::WritableDir, StorageError> {
+        self.outer_dir(category)
     }
 
-    fn writable_config(&mut self) -> Result<Self::WritableDir, OuterDirectoryError> {
-        Ok(Dir::new(
-            "".to_string(),
-            "config".into(),
-            self.web_storage.clone(),
-        ))
+    async fn space(&self) -> std::io::Result<Space> {
+        // Browsers store strings as UTF-16 and count both keys and values against the quota.
+        let mut used = 0;
+        for key in keys(&self.web_storage)? {
+            let value = self.web_storage.get_item(&key).unwrap_or(None);
+            let len = key.encode_utf16().count() + value.map_or(0, |v| v.encode_utf16().count());
+            used += 2 * len as u64;
+        }
+        Ok(Space {
+            used: Some(used),
+            // Local storage has its own limit, usually 5MB, which browsers do not report.
+            available: space::available()
+                .await?
+                .map(|available| available.min(LOCAL_STORAGE_LIMIT.saturating_sub(used))),
+        })
     }
 
-    fn writable_cache(&mut self) -> Result<Self::WritableDir, OuterDirectoryError> {
-        Ok(Dir::new(
-            "".to_string(),
-            "cache".into(),
-            self.web_storage.clone(),
-        ))
+    async fn request_persistence(&mut self) -> std::io::Result<bool> {
+        space::persist().await
     }
 }
 
@@ -223,7 +351,9 @@ impl super::super::Storage for Storage {
 mod tests {
     use super::super::super::generic::tests as generic_tests;
     use super::*;
+    use crate::storage::{Change, ChangeKind, Dir as _, File as _, ParentDir as _, Storage as _};
     use wasm_bindgen_test::wasm_bindgen_test;
+    use web_sys::{StorageEvent, StorageEventInit};
 
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
@@ -245,4 +375,147 @@ mod tests {
     fn file_uniqueness() {
         generic_tests::file_uniqueness(make_storage());
     }
+
+    #[wasm_bindgen_test]
+    fn categories() {
+        generic_tests::categories(make_storage());
+    }
+
+    #[wasm_bindgen_test]
+    fn locking() {
+        generic_tests::locking(make_storage());
+    }
+
+    #[wasm_bindgen_test]
+    fn metadata() {
+        generic_tests::metadata(make_storage());
+    }
+
+    #[wasm_bindgen_test]
+    fn missing_file() {
+        generic_tests::missing_file(make_storage());
+    }
+
+    #[wasm_bindgen_test]
+    fn commit_and_abort() {
+        generic_tests::commit_and_abort(make_storage());
+    }
+
+    #[wasm_bindgen_test]
+    fn copy_and_move() {
+        generic_tests::copy_and_move(make_storage());
+    }
+
+    #[wasm_bindgen_test]
+    fn removal() {
+        generic_tests::removal(make_storage());
+    }
+
+    #[wasm_bindgen_test]
+    fn listing() {
+        generic_tests::listing(make_storage());
+    }
+
+    #[wasm_bindgen_test]
+    fn watching() {
+        // Browsers only tell other tabs about changes, so pretend to be one.
+        let storage = make_storage();
+        let dir = storage.data().unwrap().subdir("watching".into());
+        let mut dir_watcher = dir.watch().unwrap();
+        let mut file_watcher = dir.file("a".into()).watch().unwrap();
+        let send = |key: &str, old_value: Option<&str>, new_value: Option<&str>| {
+            let init = StorageEventInit::new();
+            init.set_key(Some(key));
+            init.set_old_value(old_value);
+            init.set_new_value(new_value);
+            init.set_storage_area(Some(&storage.web_storage));
+            let event = StorageEvent::new_with_event_init_dict("storage", &init).unwrap();
+            web_sys::window().unwrap().dispatch_event(&event).unwrap();
+        };
+        send("data/watching/a", None, Some("1"));
+        send("data/watching/sub/b", None, Some("2"));
+        send("data/watching/b", Some("1"), Some("2"));
+        send("data/other", None, Some("1"));
+        send("meta:data/watching/a", None, Some("1 0"));
+        assert_eq!(
+            dir_watcher.changes().unwrap(),
+            vec![
+                Change::new("a", ChangeKind::Created),
+                Change::new("sub/b", ChangeKind::Created),
+                Change::new("b", ChangeKind::Modified),
+            ]
+        );
+        send("data/watching/a", Some("1"), None);
+        assert_eq!(
+            dir_watcher.changes().unwrap(),
+            vec![Change::new("a", ChangeKind::Removed)]
+        );
+        assert_eq!(file_watcher.changes().unwrap(), vec![]);
+    }
+
+    #[wasm_bindgen_test]
+    async fn space() {
+        generic_tests::space(make_storage()).await;
+    }
+
+    #[wasm_bindgen_test]
+    fn names() {
+        generic_tests::names(make_storage());
+    }
+
+    #[wasm_bindgen_test]
+    fn value_kinds() {
+        use super::super::super::{Storage as _, WritableDir as _};
+        use std::io::Read;
+        fn read(file: &impl crate::storage::File, text: bool) -> Vec<u8> {
+            let mut got = Vec::new();
+            if text {
+                file.read_text().unwrap().read_to_end(&mut got).unwrap();
+            } else {
+                file.read_binary().unwrap().read_to_end(&mut got).unwrap();
+            }
+            got
+        }
+        let mut storage = make_storage();
+        let mut dir = storage.writable_data().unwrap();
+        let mut file = dir.writable_file("kinds".into());
+
+        // Text that looks like a packed binary value.
+        let text = "\u{E000}\u{E001} text".as_bytes();
+        generic_tests::write_text(&mut file, text);
+        assert_eq!(read(&file, false), text);
+        assert_eq!(file.metadata().unwrap().len, text.len() as u64);
+
+        let binary = [0, 0xE0, 0x80, 0xFF];
+        generic_tests::write_binary(&mut file, &binary);
+        assert_eq!(read(&file, true), binary);
+        assert_eq!(file.metadata().unwrap().len, 4);
+
+        // Values from older versions, without a sidecar.
+        metadata::remove(&storage.web_storage, "data/kinds").unwrap();
+        storage
+            .web_storage
+            .set_item("data/kinds", "\u{0}\u{FF}")
+            .unwrap();
+        assert_eq!(file.metadata().unwrap().len, 2);
+        assert_eq!(read(&file, false), [0, 0xFF]);
+        dir.clear().unwrap();
+    }
+
+    #[wasm_bindgen_test]
+    fn clear_leaves_other_keys() {
+        use super::super::super::{Storage as _, WritableDir as _};
+        let mut storage = make_storage();
+        for key in ["unrelated", "database/file"] {
+            storage.web_storage.set_item(key, "kept").unwrap();
+        }
+        storage.writable_data().unwrap().clear().unwrap();
+        for key in ["unrelated", "database/file"] {
+            assert_eq!(
+                storage.web_storage.get_item(key).unwrap(),
+                Some("kept".to_string())
+            );
+            storage.web_storage.remove_item(key).unwrap();
+        }
+    }
 }
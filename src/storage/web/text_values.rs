@@ -1,23 +1,19 @@
+use super::binary_values::contents;
+use super::metadata::{self, Kind};
+use super::space;
+use crate::storage::generic::{finished_writer, Commit};
 use std::io::{Cursor, Read, Write};
 use web_sys::Storage as WebStorage;
 
 pub struct TextStorageReader {
-    cursor: Cursor<String>,
+    cursor: Cursor<Vec<u8>>,
 }
 
 impl TextStorageReader {
     pub fn new(web_storage: &WebStorage, key: &str) -> std::io::Result<Self> {
-        let value = web_storage.get_item(key).unwrap_or(None);
-        if let Some(value) = value {
-            Ok(Self {
-                cursor: Cursor::new(value),
-            })
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "cannot find stored value",
-            ))
-        }
+        Ok(Self {
+            cursor: Cursor::new(contents(web_storage, key, Kind::Text)?),
+        })
     }
 }
 
@@ -27,10 +23,11 @@ impl Read for TextStorageReader {
     }
 }
 
+/// Collects contents which replace the stored value when committed.
 pub struct TextStorageWriter {
     web_storage: WebStorage,
     key: String,
-    buf: Vec<u8>,
+    buf: Option<Vec<u8>>,
 }
 
 impl TextStorageWriter {
@@ -38,28 +35,33 @@ impl TextStorageWriter {
         Ok(Self {
             web_storage: web_storage.clone(),
             key: key.to_string(),
-            buf: Vec::new(),
+            buf: Some(Vec::new()),
         })
     }
 }
 
 impl Write for TextStorageWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.buf.write(buf)
+        self.buf.as_mut().ok_or_else(finished_writer)?.write(buf)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
-        let value = std::str::from_utf8(self.buf.as_slice())
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "cannot convert utf8"))?
-            .to_string();
-        self.web_storage
-            .set_item(&self.key, &value)
-            .map_err(|_| std::io::Error::new(std::io::ErrorKind::Other, "cannot store value"))
+        Ok(())
     }
 }
 
-impl Drop for TextStorageWriter {
-    fn drop(&mut self) {
-        let _ = self.flush();
+impl Commit for TextStorageWriter {
+    fn commit(&mut self) -> std::io::Result<()> {
+        let buf = self.buf.take().ok_or_else(finished_writer)?;
+        let value = std::str::from_utf8(&buf)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        self.web_storage
+            .set_item(&self.key, value)
+            .map_err(space::store_failed)?;
+        metadata::store(&self.web_storage, &self.key, buf.len(), Kind::Text)
+    }
+
+    fn abort(&mut self) {
+        self.buf = None;
     }
 }
@@ -0,0 +1,65 @@
+//! Watching for changes using the `storage` event. Browsers only send this to other tabs, so
+//! changes made in the same tab are not seen.
+
+use crate::storage::error::StorageError;
+use crate::storage::generic::{ChangeKind, Watcher};
+use crate::storage::watch::{push_change, Scope};
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::{Storage as WebStorage, StorageEvent, Window};
+
+static EVENT: &str = "storage";
+
+/// Stops listening when dropped along with the watcher.
+struct Listener {
+    window: Window,
+    closure: Closure<dyn FnMut(StorageEvent)>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = self
+            .window
+            .remove_event_listener_with_callback(EVENT, self.closure.as_ref().unchecked_ref());
+    }
+}
+
+pub fn watch(web_storage: &WebStorage, scope: Scope) -> std::io::Result<Watcher> {
+    let window = web_sys::window()
+        .ok_or_else(|| std::io::Error::from(StorageError::backend("no window")))?;
+    let changes = Rc::new(RefCell::new(Vec::new()));
+    let closure = {
+        let changes = changes.clone();
+        let web_storage = web_storage.clone();
+        Closure::<dyn FnMut(StorageEvent)>::new(move |event: StorageEvent| {
+            let same_storage = event
+                .storage_area()
+                .is_some_and(|area| js_sys::Object::is(&area, &web_storage));
+            // There is no key when all of storage is cleared, which we never do ourselves.
+            let Some(key) = event.key().filter(|_| same_storage) else {
+                return;
+            };
+            let Some(name) = scope.name(&key) else {
+                return;
+            };
+            let kind = match (event.old_value(), event.new_value()) {
+                (None, _) => ChangeKind::Created,
+                (_, None) => ChangeKind::Removed,
+                _ => ChangeKind::Modified,
+            };
+            push_change(&mut changes.borrow_mut(), name, kind);
+        })
+    };
+    window
+        .add_event_listener_with_callback(EVENT, closure.as_ref().unchecked_ref())
+        .map_err(|_| {
+            std::io::Error::from(StorageError::backend("cannot listen for storage events"))
+        })?;
+    let listener = Listener { window, closure };
+    Ok(Watcher::new(move || {
+        let _listening = &listener;
+        Ok(std::mem::take(&mut *changes.borrow_mut()))
+    }))
+}